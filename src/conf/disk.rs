@@ -1,10 +1,13 @@
+use super::keyed::*;
 pub use super::part::*;
 use super::validate::*;
 use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
 use std::fmt;
 
 /// The possible actions that can be performed on a disk
-#[derive(Serialize, Debug, PartialEq)]
+#[derive(Serialize, Debug, PartialEq, Clone)]
+#[serde(rename_all = "lowercase")]
 pub enum DiskAction {
     Locked,
     Keep,
@@ -13,6 +16,9 @@ pub enum DiskAction {
 }
 const DISK_ACTIONS: &'static [&'static str] = &["locked", "keep", "alter", "new"];
 
+/// The amount of primary (and extended) partitions an msdos table can hold
+pub const MBR_MAX_PRIMARY: usize = 4;
+
 ///	Matches a string of the disk action value to the correct DiskAction
 /// # Arguments
 /// * `value` - The value to match
@@ -28,14 +34,32 @@ fn match_disk_action(value: &str) -> Result<DiskAction, &str> {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Serialize, Debug)]
 pub struct DiskConf {
     pub path: String,
     pub action: DiskAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub table: Option<String>,
+    /// The boundary new partitions get aligned to, 1 MiB or the optimal I/O size of the disk if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alignment: Option<PartSize>,
     pub partitions: Vec<PartConf>,
 }
 
+/// The fields of a DiskConf as they appear in the installfile
+/// The path can be a field or the key of the disk ('- /dev/sda: {...}')
+#[derive(Deserialize)]
+struct RawDiskConf {
+    path: Option<String>,
+    action: Option<DiskAction>,
+    table: Option<String>,
+    alignment: Option<PartSize>,
+    partitions: Option<Vec<PartConf>>,
+
+    #[serde(flatten)]
+    keyed: HashMap<String, serde_json::Value>,
+}
+
 impl Validate for DiskConf {
     fn validate(&mut self) -> Result<(), ValidationError> {
         //The partition table can only be altered in 'new' mode
@@ -55,6 +79,57 @@ impl Validate for DiskConf {
             }
         }
 
+        //Partitions can only be aligned to a fixed size
+        match &self.alignment {
+            None => (),
+            Some(PartSize::Size(count, unit))
+                if data_size_bytes(*count, unit).is_some_and(|b| b > 0) => {}
+            Some(_) => {
+                return Err(ValidationError::new(
+                    self.path.as_str(),
+                    "'alignment' needs to be a size (e.g. '1M')",
+                ))
+            }
+        }
+
+        //Named partitions get their index from their path, the others count up from the previous one
+        //Logical partitions are numbered separately, starting at 5
+        let mut last_index: usize = 0;
+        let mut last_logical: usize = MBR_MAX_PRIMARY;
+        for part in self.partitions.iter_mut() {
+            let logical = part.get_kind() == PartKind::Logical;
+            part.index = match &part.path {
+                Some(p) => match get_part_index(&self.path, p) {
+                    Some(i) => i,
+                    None => {
+                        return Err(ValidationError::new(
+                            self.path.as_str(),
+                            format!("'{}' is not a partition of this disk", p).as_str(),
+                        ))
+                    }
+                },
+                None if logical => last_logical + 1,
+                None => last_index + 1,
+            };
+            match logical {
+                true => last_logical = part.index,
+                false => last_index = part.index,
+            }
+        }
+
+        self.validate_msdos()?;
+
+        //Every partition can only be configured once
+        for (i, part) in self.partitions.iter().enumerate() {
+            if self.partitions[..i].iter().any(|p| p.index == part.index) {
+                let context = format!("{} -> {}", self.path, part.index);
+                return Err(ValidationError::new(
+                    context.as_str(),
+                    "Partition is configured multiple times",
+                ));
+            }
+        }
+
         //If we are in 'locked' mode, do not allow any modifications to partitions
         if self.action == DiskAction::Locked {
             for part in &self.partitions {
@@ -68,9 +143,28 @@ impl Validate for DiskConf {
             }
         }
 
+        //A partition using all the remaining space has to be the last one,
+        //only the logical partitions inside an extended partition can follow it
+        for (index, part) in self.partitions.iter().enumerate() {
+            match part.size {
+                Some(PartSize::Total(PartTotal::Max)) => {
+                    let followed = self.partitions[index + 1..].iter().any(|p| {
+                        part.get_kind() != PartKind::Extended || p.get_kind() != PartKind::Logical
+                    });
+                    if followed {
+                        let context = format!("{} -> {}", self.path, part.index);
+                        return Err(ValidationError::new(
+                            context.as_str(),
+                            "Partitions with size 'max' can't be followed by other partitions",
+                        ));
+                    }
+                }
+                _ => (),
+            }
+        }
+
         //Then validate the partitions
-        for (index, part) in self.partitions.iter_mut().enumerate() {
-            part.index = index + 1;
+        for part in self.partitions.iter_mut() {
             part.validate()?
         }
 
@@ -78,6 +172,162 @@ impl Validate for DiskConf {
     }
 }
 
+impl DiskConf {
+    /// Enforces the limits of msdos partition tables if the disk has one or uses extended or logical partitions
+    fn validate_msdos(&self) -> Result<(), ValidationError> {
+        let uses_kinds = self
+            .partitions
+            .iter()
+            .any(|p| p.get_kind() != PartKind::Primary);
+
+        match self.table.as_deref() {
+            Some("msdos") => (),
+            Some(t) if uses_kinds => {
+                return Err(ValidationError::new(
+                    self.path.as_str(),
+                    format!(
+                        "Extended and logical partitions need an msdos table, not '{}'",
+                        t
+                    )
+                    .as_str(),
+                ))
+            }
+            _ if !uses_kinds => return Ok(()),
+            _ => (),
+        }
+
+        for part in &self.partitions {
+            let context = format!("{} -> {}", self.path, part.index);
+            let msg = match part.get_kind() {
+                PartKind::Logical if part.index <= MBR_MAX_PRIMARY => {
+                    "Logical partitions are numbered from 5"
+                }
+                PartKind::Primary | PartKind::Extended if part.index > MBR_MAX_PRIMARY => {
+                    "An msdos table holds 4 primary partitions, use 'kind: logical' for more"
+                }
+                _ => continue,
+            };
+            return Err(ValidationError::new(context.as_str(), msg));
+        }
+
+        let extended = self
+            .partitions
+            .iter()
+            .filter(|p| p.get_kind() == PartKind::Extended)
+            .count();
+        if extended > 1 {
+            return Err(ValidationError::new(
+                self.path.as_str(),
+                "An msdos table can only hold one extended partition",
+            ));
+        }
+
+        //A new table only has the extended partition seed creates
+        let logical = self
+            .partitions
+            .iter()
+            .any(|p| p.get_kind() == PartKind::Logical);
+        if self.action == DiskAction::New && logical && extended == 0 {
+            return Err(ValidationError::new(
+                self.path.as_str(),
+                "Logical partitions need an extended partition",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns what separates the partition number from the path of the disk
+/// # Arguments
+/// * `disk_path` - The path of the disk
+/// # Returns
+/// '-part' for udev links ('/dev/disk/by-id/...-part1'),
+/// 'p' for disks ending in a digit ('/dev/nvme0n1p1', '/dev/mmcblk0p1', '/dev/loop0p1'), else nothing
+pub fn get_part_separator(disk_path: &str) -> &'static str {
+    if disk_path.starts_with("/dev/disk/") {
+        return "-part";
+    }
+
+    match disk_path.ends_with(|c: char| c.is_ascii_digit()) {
+        true => "p",
+        false => "",
+    }
+}
+
+/// Determines the index of a partition from its device path
+/// # Arguments
+/// * `disk_path` - The path of the disk the partition resides on
+/// * `part_path` - The path of the partition (e.g. '/dev/sda1', '/dev/nvme0n1p1')
+/// # Returns
+/// The index of the partition, None if the path is not a partition of the disk
+pub fn get_part_index(disk_path: &str, part_path: &str) -> Option<usize> {
+    let rest = part_path
+        .strip_prefix(disk_path)?
+        .strip_prefix(get_part_separator(disk_path))?;
+
+    match rest.parse::<usize>() {
+        Ok(0) => None,
+        Ok(i) => Some(i),
+        Err(_) => None,
+    }
+}
+
+//
+//	A custom deserializer for DiskConf
+//
+impl<'de> Deserialize<'de> for DiskConf {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = RawDiskConf::deserialize(deserializer)?;
+
+        let (path, nested) = match take_keyed_entry::<RawDiskConf, D::Error>(raw.keyed, "disk")? {
+            Some((key, nested)) => {
+                if raw.path.is_some() {
+                    return Err(de::Error::custom(format!(
+                        "Disk {} has a 'path' and a key",
+                        key
+                    )));
+                }
+                (key, nested)
+            }
+            None => match raw.path {
+                Some(p) => (p, None),
+                None => return Err(de::Error::missing_field("path")),
+            },
+        };
+
+        //Nested fields take precedence over the siblings of the path key
+        let (action, table, alignment, partitions) = match nested {
+            Some(n) => (
+                n.action.or(raw.action),
+                n.table.or(raw.table),
+                n.alignment.or(raw.alignment),
+                n.partitions.or(raw.partitions),
+            ),
+            None => (raw.action, raw.table, raw.alignment, raw.partitions),
+        };
+
+        Ok(DiskConf {
+            action: match action {
+                Some(a) => a,
+                None => {
+                    return Err(de::Error::custom(format!(
+                        "Disk {}: missing field `action`",
+                        path
+                    )))
+                }
+            },
+            table,
+            alignment,
+            partitions: partitions.unwrap_or_default(),
+            path,
+        })
+    }
+}
+
 //
 //	A custom deserializer for DiskAction
 //
@@ -109,3 +359,90 @@ impl<'de> de::Visitor<'de> for DiskActionVisitor {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::fixture;
+
+    /// Validates a new disk with the table and the partitions
+    /// # Arguments
+    /// * `table` - The partition table of the disk
+    /// * `partitions` - The partitions, one flow mapping per partition
+    fn validate(table: &str, partitions: &[&str]) -> Result<Vec<usize>, String> {
+        let partitions: Vec<String> = partitions
+            .iter()
+            .map(|p| format!("      - {}\n", p))
+            .collect();
+        let mut file = fixture::parse(&format!(
+            "disks:\n  - /dev/sdx:\n    action: new\n    table: {}\n    partitions:\n{}",
+            table,
+            partitions.concat()
+        ));
+
+        match file.validate() {
+            Ok(_) => Ok(file.seed.disks[0]
+                .partitions
+                .iter()
+                .map(|p| p.index)
+                .collect()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    const PRIMARY: &str = "{action: create, size: 1G}";
+    const EXTENDED: &str = "{action: create, size: max, kind: extended}";
+    const LOGICAL: &str = "{action: create, size: 1G, kind: logical}";
+    const LOGICAL_MAX: &str = "{action: create, size: max, kind: logical}";
+
+    #[test]
+    fn logical_partitions_get_numbered_from_5() {
+        assert_eq!(
+            validate("msdos", &[PRIMARY, EXTENDED, LOGICAL, LOGICAL_MAX]),
+            Ok(vec![1, 2, 5, 6])
+        );
+    }
+
+    #[test]
+    fn msdos_holds_4_primary_partitions() {
+        assert!(validate("msdos", &[PRIMARY; 4]).is_ok());
+        let err = validate("msdos", &[PRIMARY; 5]).unwrap_err();
+        assert!(err.contains("4 primary partitions"), "{}", err);
+
+        let err = validate(
+            "msdos",
+            &[PRIMARY, "{path: /dev/sdx5, action: create, size: 1G}"],
+        )
+        .unwrap_err();
+        assert!(err.contains("4 primary partitions"), "{}", err);
+    }
+
+    #[test]
+    fn logical_partitions_need_an_extended_one_on_msdos() {
+        let err = validate("gpt", &[EXTENDED, LOGICAL]).unwrap_err();
+        assert!(err.contains("need an msdos table"), "{}", err);
+
+        let err = validate("msdos", &[PRIMARY, LOGICAL]).unwrap_err();
+        assert!(err.contains("need an extended partition"), "{}", err);
+
+        let err = validate("msdos", &[EXTENDED, EXTENDED]).unwrap_err();
+        assert!(err.contains("one extended partition"), "{}", err);
+
+        let logical = "{path: /dev/sdx3, action: create, size: 1G, kind: logical}";
+        let err = validate("msdos", &[EXTENDED, logical]).unwrap_err();
+        assert!(err.contains("numbered from 5"), "{}", err);
+    }
+
+    #[test]
+    fn max_is_the_last_partition() {
+        let max = "{action: create, size: max}";
+        let err = validate("gpt", &[max, PRIMARY]).unwrap_err();
+        assert!(err.contains("size 'max'"), "{}", err);
+        assert!(err.contains("/dev/sdx -> 1"), "{}", err);
+
+        //Only logical partitions can follow the extended partition using the remaining space
+        assert!(validate("msdos", &[EXTENDED, LOGICAL, LOGICAL]).is_ok());
+        assert!(validate("msdos", &[EXTENDED, PRIMARY]).is_err());
+        assert!(validate("msdos", &[EXTENDED, LOGICAL_MAX, LOGICAL]).is_err());
+    }
+}
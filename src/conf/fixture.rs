@@ -0,0 +1,50 @@
+//Installfiles shared by the tests of the configuration and its users
+
+use super::installfile::*;
+
+/// The settings every installfile needs, the tests replace what they check
+const INSTALLFILE: &str = r#"
+version: 1
+seed:
+  env: {}
+  disks:
+    - /dev/sdx:
+      action: new
+      table: gpt
+      partitions:
+        - size: max
+          action: create
+          fs: ext4
+          mount: /
+  installation:
+    packages: []
+  fstab:
+    mode: uuid
+  systemd: {}
+"#;
+
+/// Parses an installfile with the provided settings of the 'seed' section
+/// Every top level key of `seed` replaces the one of the default installfile
+/// # Arguments
+/// * `seed` - The YAML mapping of the settings to replace
+pub fn parse(seed: &str) -> InstallFile {
+    let mut file: serde_yaml::Value = serde_yaml::from_str(INSTALLFILE).unwrap();
+    let replace: serde_yaml::Mapping = serde_yaml::from_str(seed).unwrap();
+
+    let section = file["seed"].as_mapping_mut().unwrap();
+    for (key, value) in replace {
+        section.insert(key, value);
+    }
+
+    let content = serde_yaml::to_string(&file).unwrap();
+    InstallFile::parse("test.yaml", &content, &InstallFileFormat::Yaml).unwrap()
+}
+
+/// Parses and validates an installfile with the provided settings of the 'seed' section
+/// # Arguments
+/// * `seed` - The YAML mapping of the settings to replace
+pub fn get_conf(seed: &str) -> SeedConf {
+    let mut file = parse(seed);
+    file.validate().unwrap();
+    file.seed
+}
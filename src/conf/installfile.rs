@@ -1,13 +1,262 @@
+use super::migrate::*;
 pub use super::seed::*;
 use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// The shebang that marks a file as a seed installfile
+pub const INSTALLFILE_SHEBANG: &str = "#!seed";
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct InstallFile {
+    pub version: u64,
     pub seed: SeedConf,
 }
 
+/// The formats an installfile can be written in
+#[derive(Debug, PartialEq)]
+pub enum InstallFileFormat {
+    Json,
+    Yaml,
+}
+
+impl InstallFileFormat {
+    /// Detects the format of an installfile
+    /// Uses the file extension first, then the '#!seed' shebang
+    /// # Arguments
+    /// * `path` - The path to the installfile
+    /// * `content` - The contents of the installfile
+    pub fn detect(path: &str, content: &str) -> InstallFileFormat {
+        match Path::new(path)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .as_deref()
+        {
+            Some("json") => return InstallFileFormat::Json,
+            Some("yaml") | Some("yml") => return InstallFileFormat::Yaml,
+            _ => (),
+        }
+
+        //The shebang is a comment in YAML, JSON does not know comments
+        let content = content.trim_start();
+        if content.starts_with(INSTALLFILE_SHEBANG) || !content.starts_with('{') {
+            InstallFileFormat::Yaml
+        } else {
+            InstallFileFormat::Json
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    file: String,
+    line: Option<usize>,
+    column: Option<usize>,
+    msg: String,
+}
+impl ParseError {
+    pub fn new(file: &str, line: Option<usize>, column: Option<usize>, msg: &str) -> ParseError {
+        //The parsers add the location to the message, it is printed separately
+        let msg = match (line, column) {
+            (Some(l), Some(c)) => msg.replacen(&format!(" at line {} column {}", l, c), "", 1),
+            _ => msg.to_owned(),
+        };
+
+        ParseError {
+            file: file.to_owned(),
+            line,
+            column,
+            msg,
+        }
+    }
+}
+impl Error for ParseError {}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(l), Some(c)) => write!(
+                f,
+                "Failed to parse InstallFile {}:{}:{}: {}",
+                self.file, l, c, self.msg
+            ),
+            _ => write!(f, "Failed to parse InstallFile {}: {}", self.file, self.msg),
+        }
+    }
+}
+
+impl InstallFile {
+    /// Parses an installfile, migrating older versions to the current one
+    /// # Arguments
+    /// * `file` - The name of the file (for context)
+    /// * `content` - The contents to parse
+    /// * `format` - The format of the contents
+    pub fn parse(
+        file: &str,
+        content: &str,
+        format: &InstallFileFormat,
+    ) -> Result<InstallFile, ParseError> {
+        let mut value: serde_json::Value = InstallFile::deserialize_str(file, content, format)?;
+
+        let version = match get_version(&value) {
+            Ok(v) => v,
+            Err(e) => return Err(ParseError::new(file, None, None, e.as_str())),
+        };
+
+        //Current files get parsed directly to keep the location in error messages
+        if version == INSTALLFILE_VERSION {
+            return InstallFile::deserialize_str(file, content, format);
+        }
+
+        match migrate(&mut value) {
+            Ok(_) => (),
+            Err(e) => return Err(ParseError::new(file, None, None, e.as_str())),
+        }
+
+        match serde_json::from_value(value) {
+            Ok(c) => Ok(c),
+            Err(e) => Err(ParseError::new(file, None, None, e.to_string().as_str())),
+        }
+    }
+
+    /// Deserializes the contents of an installfile
+    /// # Arguments
+    /// * `file` - The name of the file (for context)
+    /// * `content` - The contents to deserialize
+    /// * `format` - The format of the contents
+    fn deserialize_str<T>(
+        file: &str,
+        content: &str,
+        format: &InstallFileFormat,
+    ) -> Result<T, ParseError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        match format {
+            InstallFileFormat::Json => match serde_json::from_str(content) {
+                Ok(c) => Ok(c),
+                Err(e) => Err(ParseError::new(
+                    file,
+                    Some(e.line()),
+                    Some(e.column()),
+                    e.to_string().as_str(),
+                )),
+            },
+            InstallFileFormat::Yaml => match serde_yaml::from_str(content) {
+                Ok(c) => Ok(c),
+                Err(e) => Err(ParseError::new(
+                    file,
+                    e.location().map(|l| l.line()),
+                    e.location().map(|l| l.column()),
+                    e.to_string().as_str(),
+                )),
+            },
+        }
+    }
+
+    /// Reads and parses an installfile, detecting its format
+    /// # Arguments
+    /// * `path` - The path to the installfile
+    pub fn from_file(path: &str) -> Result<InstallFile, ParseError> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => return Err(ParseError::new(path, None, None, e.to_string().as_str())),
+        };
+
+        let format = InstallFileFormat::detect(path, &content);
+        debug!("Parsing {} as {:?}", path, format);
+
+        InstallFile::parse(path, &content, &format)
+    }
+}
+
 impl Validate for InstallFile {
     fn validate(&mut self) -> Result<(), ValidationError> {
         self.seed.validate()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documented_examples_are_equal() {
+        let json = InstallFile::parse(
+            "install.json",
+            include_str!("../../docs/install.json"),
+            &InstallFileFormat::Json,
+        )
+        .unwrap();
+        let yaml = InstallFile::parse(
+            "installation_file.yaml",
+            include_str!("../../docs/installation_file.yaml"),
+            &InstallFileFormat::Yaml,
+        )
+        .unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&json).unwrap(),
+            serde_json::to_value(&yaml).unwrap()
+        );
+    }
+
+    #[test]
+    fn detect_uses_extension_first() {
+        assert_eq!(
+            InstallFileFormat::detect("install.json", "#!seed"),
+            InstallFileFormat::Json
+        );
+        assert_eq!(
+            InstallFileFormat::detect("install.YAML", "{}"),
+            InstallFileFormat::Yaml
+        );
+        assert_eq!(
+            InstallFileFormat::detect("install.yml", "{}"),
+            InstallFileFormat::Yaml
+        );
+    }
+
+    #[test]
+    fn detect_falls_back_to_content() {
+        assert_eq!(
+            InstallFileFormat::detect("install", "  {\"version\": 1}"),
+            InstallFileFormat::Json
+        );
+        assert_eq!(
+            InstallFileFormat::detect("install", "#!seed\nversion: 1"),
+            InstallFileFormat::Yaml
+        );
+        assert_eq!(
+            InstallFileFormat::detect("install.txt", "version: 1"),
+            InstallFileFormat::Yaml
+        );
+    }
+
+    #[test]
+    fn json_error_has_location() {
+        let err = InstallFile::parse(
+            "test.json",
+            "{\n  \"version\": 1,\n  \"seed\": }\n}",
+            &InstallFileFormat::Json,
+        )
+        .unwrap_err();
+        assert_eq!((err.line, err.column), (Some(3), Some(11)));
+        assert!(!err.msg.contains(" at line "));
+        assert!(err
+            .to_string()
+            .starts_with("Failed to parse InstallFile test.json:3:11: "));
+    }
+
+    #[test]
+    fn yaml_error_has_location() {
+        let err = InstallFile::parse(
+            "test.yaml",
+            "version: 1\nseed:\n  env: [\n",
+            &InstallFileFormat::Yaml,
+        )
+        .unwrap_err();
+        assert_eq!((err.line, err.column), (Some(4), Some(1)));
+        assert!(!err.msg.contains(" at line "));
+    }
+}
@@ -0,0 +1,152 @@
+use serde::de::{self, DeserializeOwned};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Extracts the device path of the keyed syntax ('- /dev/sda: {...}') from the remaining fields
+/// The fields of the entry can be siblings of the path key or its value
+/// # Arguments
+/// * `keyed` - The fields that did not match any known field
+/// * `what` - What is being deserialized (for context)
+/// # Returns
+/// The path and the nested fields, if the keyed syntax was used
+pub fn take_keyed_entry<T, E>(
+    keyed: HashMap<String, Value>,
+    what: &str,
+) -> Result<Option<(String, Option<T>)>, E>
+where
+    T: DeserializeOwned,
+    E: de::Error,
+{
+    let mut res: Option<(String, Option<T>)> = None;
+
+    for (key, value) in keyed {
+        if !key.starts_with('/') {
+            warn!("{}: Ignoring unknown field '{}'", what, key);
+            continue;
+        }
+
+        if let Some((path, _)) = &res {
+            return Err(de::Error::custom(format!(
+                "{} can only have one path, got '{}' and '{}'",
+                what, path, key
+            )));
+        }
+
+        let nested = match value {
+            Value::Null => None,
+            Value::Object(_) => match serde_json::from_value(value) {
+                Ok(n) => Some(n),
+                Err(e) => return Err(de::Error::custom(format!("{}: {}", key, e))),
+            },
+            other => {
+                return Err(de::Error::custom(format!(
+                    "{}: Expected the fields of the {}, got {}",
+                    key, what, other
+                )))
+            }
+        };
+
+        res = Some((key, nested));
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf::disk::*;
+
+    fn parse_disk(yaml: &str) -> Result<DiskConf, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    #[test]
+    fn disk_path_can_be_a_field_or_the_key() {
+        let field = parse_disk("path: /dev/sdx\naction: keep\n").unwrap();
+        let sibling = parse_disk("/dev/sdx:\naction: keep\n").unwrap();
+        let nested = parse_disk("/dev/sdx:\n  action: keep\n").unwrap();
+
+        for disk in [field, sibling, nested] {
+            assert_eq!(disk.path, "/dev/sdx");
+            assert_eq!(disk.action, DiskAction::Keep);
+            assert!(disk.partitions.is_empty());
+        }
+    }
+
+    #[test]
+    fn nested_disk_fields_take_precedence() {
+        let disk = parse_disk("/dev/sdx:\n  action: new\naction: keep\n").unwrap();
+        assert_eq!(disk.action, DiskAction::New);
+    }
+
+    #[test]
+    fn ambiguous_disk_paths_are_an_error() {
+        let err = parse_disk("path: /dev/sdx\n/dev/sdy:\naction: keep\n").unwrap_err();
+        assert!(
+            err.to_string().contains("has a 'path' and a key"),
+            "{}",
+            err
+        );
+        let err = parse_disk("/dev/sdx:\n/dev/sdy:\naction: keep\n").unwrap_err();
+        assert!(
+            err.to_string().contains("can only have one path"),
+            "{}",
+            err
+        );
+        assert!(parse_disk("/dev/sdx: keep\n").is_err());
+        assert!(parse_disk("action: keep\n").is_err());
+        assert!(parse_disk("/dev/sdx:\n").is_err());
+    }
+
+    #[test]
+    fn partition_path_can_be_a_field_or_the_key() {
+        let disk = parse_disk(
+            r#"
+/dev/sdx:
+  action: keep
+  partitions:
+    - path: /dev/sdx1
+      action: keep
+    - /dev/sdx2:
+      action: keep
+      mount: /
+    - /dev/sdx3:
+        action: keep
+        mount: /home
+      mount: /srv
+"#,
+        )
+        .unwrap();
+
+        let paths: Vec<Option<&str>> = disk.partitions.iter().map(|p| p.path.as_deref()).collect();
+        assert_eq!(
+            paths,
+            vec![Some("/dev/sdx1"), Some("/dev/sdx2"), Some("/dev/sdx3")]
+        );
+        assert_eq!(disk.partitions[1].mount.as_deref(), Some("/"));
+        assert_eq!(disk.partitions[2].mount.as_deref(), Some("/home"));
+    }
+
+    #[test]
+    fn ambiguous_partition_paths_are_an_error() {
+        let disk = |partition: &str| {
+            parse_disk(&format!(
+                "/dev/sdx:\n  action: keep\n  partitions:\n    - {}\n      action: keep\n",
+                partition
+            ))
+        };
+        assert!(disk("/dev/sdx1:").is_ok());
+        let err = disk("path: /dev/sdx1\n      /dev/sdx2:").unwrap_err();
+        assert!(
+            err.to_string().contains("has a 'path' and a key"),
+            "{}",
+            err
+        );
+        let err = disk("/dev/sdx1:\n      /dev/sdx2:").unwrap_err();
+        assert!(
+            err.to_string().contains("can only have one path"),
+            "{}",
+            err
+        );
+    }
+}
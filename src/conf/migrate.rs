@@ -0,0 +1,110 @@
+use serde_json::Value;
+
+/// The installfile version this seed works with
+pub const INSTALLFILE_VERSION: u64 = 1;
+
+/// The oldest installfile version that can still be migrated
+pub const INSTALLFILE_MIN_VERSION: u64 = 1;
+
+/// A migration upgrades the raw installfile by exactly one version
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+/// The migrations, indexed by the version they upgrade from, starting at INSTALLFILE_MIN_VERSION
+/// When changing the schema, bump INSTALLFILE_VERSION and append the migration from the old version
+const MIGRATIONS: &'static [Migration] = &[];
+
+/// Reads the 'version' field of a raw installfile
+/// # Arguments
+/// * `value` - The raw installfile
+pub fn get_version(value: &Value) -> Result<u64, String> {
+    match value.get("version") {
+        Some(v) => match v.as_u64() {
+            Some(v) => Ok(v),
+            None => Err(format!(
+                "'version' needs to be a positive number, got {}",
+                v
+            )),
+        },
+        None => Err("Missing required field 'version'".to_owned()),
+    }
+}
+
+/// Checks if seed can handle the installfile version
+/// # Arguments
+/// * `version` - The version of the installfile
+pub fn check_version(version: u64) -> Result<(), String> {
+    if version > INSTALLFILE_VERSION {
+        return Err(format!(
+            "Installfile version {} is newer than the supported version {}, please update seed",
+            version, INSTALLFILE_VERSION
+        ));
+    }
+
+    if version < INSTALLFILE_MIN_VERSION {
+        return Err(format!(
+            "Installfile version {} is no longer supported, the oldest supported version is {}",
+            version, INSTALLFILE_MIN_VERSION
+        ));
+    }
+
+    Ok(())
+}
+
+/// Upgrades a raw installfile to the current version
+/// # Arguments
+/// * `value` - The raw installfile to upgrade in place
+pub fn migrate(value: &mut Value) -> Result<(), String> {
+    let mut version = get_version(value)?;
+    check_version(version)?;
+
+    while version < INSTALLFILE_VERSION {
+        info!(
+            "Migrating installfile from version {} to {}",
+            version,
+            version + 1
+        );
+        MIGRATIONS[(version - INSTALLFILE_MIN_VERSION) as usize](value)
+            .map_err(|e| format!("Migration from version {} failed: {}", version, e))?;
+
+        version += 1;
+        value["version"] = Value::from(version);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn every_old_version_has_a_migration() {
+        assert_eq!(
+            MIGRATIONS.len() as u64,
+            INSTALLFILE_VERSION - INSTALLFILE_MIN_VERSION
+        );
+    }
+
+    #[test]
+    fn current_version_stays_untouched() {
+        let mut value = json!({"version": INSTALLFILE_VERSION, "seed": {}});
+        migrate(&mut value).unwrap();
+        assert_eq!(value, json!({"version": INSTALLFILE_VERSION, "seed": {}}));
+    }
+
+    #[test]
+    fn missing_or_invalid_version_is_an_error() {
+        assert!(migrate(&mut json!({"seed": {}})).is_err());
+        assert!(migrate(&mut json!({"version": "1"})).is_err());
+        assert!(migrate(&mut json!({"version": -1})).is_err());
+    }
+
+    #[test]
+    fn unsupported_versions_are_an_error() {
+        assert!(check_version(0).is_err());
+        assert!(check_version(INSTALLFILE_VERSION + 1).is_err());
+        assert!(migrate(&mut json!({"version": 0})).is_err());
+        assert!(migrate(&mut json!({"version": INSTALLFILE_VERSION + 1})).is_err());
+    }
+}
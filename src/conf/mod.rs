@@ -1,5 +1,9 @@
 pub mod disk;
+#[cfg(test)]
+pub mod fixture;
 pub mod installfile;
+pub mod keyed;
+pub mod migrate;
 pub mod part;
 pub mod seed;
 pub mod size;
@@ -1,11 +1,16 @@
+use super::keyed::*;
 pub use super::size::*;
 use super::validate::{Validate, ValidationError};
+use crate::target::symlinks::normalize_target_path;
 use serde::de::{self, Visitor};
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
 use std::fmt;
+use std::path::Path;
 use sys_mount::*;
 
 #[derive(Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
 pub enum PartTotal {
     Min,
     Max,
@@ -27,6 +32,7 @@ fn match_part_total(value: &str) -> Result<PartTotal, &str> {
 
 /// The possible actions that can be performed on a partition
 #[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum PartAction {
     Keep,
     Format,
@@ -35,6 +41,17 @@ pub enum PartAction {
 }
 const PART_ACTIONS: &'static [&'static str] = &["keep", "format", "resize", "create"];
 
+impl fmt::Display for PartAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartAction::Keep => write!(f, "keep"),
+            PartAction::Format => write!(f, "format"),
+            PartAction::Resize => write!(f, "resize"),
+            PartAction::Create => write!(f, "create"),
+        }
+    }
+}
+
 ///	Matches a string of the partition action value to the correct PartAction
 /// # Arguments
 /// * `value` - The value to match
@@ -50,20 +67,275 @@ fn match_part_action(value: &str) -> Result<PartAction, &str> {
     }
 }
 
+/// The type of a partition, well known types or the type GUID of a GPT partition
+#[derive(Debug, PartialEq)]
+pub enum PartType {
+    Esp,
+    BiosGrub,
+    Swap,
+    Linux,
+    LinuxRootX86_64,
+    Home,
+    Guid(String),
+}
+const PART_TYPES: &'static [&'static str] = &[
+    "esp",
+    "bios_grub",
+    "swap",
+    "linux",
+    "linux-root-x86-64",
+    "home",
+    "<type GUID>",
+];
+
+impl PartType {
+    /// Returns the GPT type GUID of the partition type
+    pub fn guid(&self) -> &str {
+        match self {
+            PartType::Esp => "C12A7328-F81F-11D2-BA4B-00A0C93EC93B",
+            PartType::BiosGrub => "21686148-6449-6E6F-744E-656564454649",
+            PartType::Swap => "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F",
+            PartType::Linux => "0FC63DAF-8483-4772-8E79-3D69D8477DE4",
+            PartType::LinuxRootX86_64 => "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709",
+            PartType::Home => "933AC7E1-2EB4-4F13-B844-0E14E2AEF915",
+            PartType::Guid(g) => g,
+        }
+    }
+}
+
+impl fmt::Display for PartType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartType::Esp => write!(f, "esp"),
+            PartType::BiosGrub => write!(f, "bios_grub"),
+            PartType::Swap => write!(f, "swap"),
+            PartType::Linux => write!(f, "linux"),
+            PartType::LinuxRootX86_64 => write!(f, "linux-root-x86-64"),
+            PartType::Home => write!(f, "home"),
+            PartType::Guid(g) => write!(f, "{}", g),
+        }
+    }
+}
+
+/// Checks if the value is a GUID ('XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX')
+/// # Arguments
+/// * `value` - The value to check
+pub fn is_guid(value: &str) -> bool {
+    let groups: Vec<&str> = value.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12])
+            .all(|(g, len)| g.len() == len && g.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+///	Matches a string of the partition type value to the correct PartType
+/// # Arguments
+/// * `value` - The value to match
+/// # Returns
+/// The PartType, else the string that was not matched
+fn match_part_type(value: &str) -> Result<PartType, &str> {
+    match value.to_lowercase().as_str() {
+        "esp" => Ok(PartType::Esp),
+        "bios_grub" => Ok(PartType::BiosGrub),
+        "swap" => Ok(PartType::Swap),
+        "linux" => Ok(PartType::Linux),
+        "linux-root-x86-64" => Ok(PartType::LinuxRootX86_64),
+        "home" => Ok(PartType::Home),
+        _ if is_guid(value) => Ok(PartType::Guid(value.to_uppercase())),
+        _ => Err(value),
+    }
+}
+
+/// The attribute flags a partition can have
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PartFlag {
+    Boot,
+    LegacyBoot,
+    Hidden,
+}
+const PART_FLAGS: &'static [&'static str] = &["boot", "legacy_boot", "hidden"];
+
+impl fmt::Display for PartFlag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartFlag::Boot => write!(f, "boot"),
+            PartFlag::LegacyBoot => write!(f, "legacy_boot"),
+            PartFlag::Hidden => write!(f, "hidden"),
+        }
+    }
+}
+
+///	Matches a string of the partition flag value to the correct PartFlag
+/// # Arguments
+/// * `value` - The value to match
+/// # Returns
+/// The PartFlag, else the string that was not matched
+fn match_part_flag(value: &str) -> Result<PartFlag, &str> {
+    match value {
+        "boot" => Ok(PartFlag::Boot),
+        "legacy_boot" => Ok(PartFlag::LegacyBoot),
+        "hidden" => Ok(PartFlag::Hidden),
+        _ => Err(value),
+    }
+}
+
+/// The kinds of partitions an msdos partition table can hold
+#[derive(Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PartKind {
+    Primary,
+    Extended,
+    Logical,
+}
+const PART_KINDS: &'static [&'static str] = &["primary", "extended", "logical"];
+
+impl fmt::Display for PartKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartKind::Primary => write!(f, "primary"),
+            PartKind::Extended => write!(f, "extended"),
+            PartKind::Logical => write!(f, "logical"),
+        }
+    }
+}
+
+///	Matches a string of the partition kind value to the correct PartKind
+/// # Arguments
+/// * `value` - The value to match
+/// # Returns
+/// The PartKind, else the string that was not matched
+fn match_part_kind(value: &str) -> Result<PartKind, &str> {
+    match value {
+        "primary" => Ok(PartKind::Primary),
+        "extended" => Ok(PartKind::Extended),
+        "logical" => Ok(PartKind::Logical),
+        _ => Err(value),
+    }
+}
+
+/// Where the passphrase of an encrypted partition comes from
+#[derive(Debug, PartialEq)]
+pub enum PassphraseSource {
+    Prompt,
+    Env(String),
+    File(String),
+}
+const PASSPHRASE_SOURCES: &'static [&'static str] = &["prompt", "env:<VAR>", "file:<path>"];
+
+impl fmt::Display for PassphraseSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PassphraseSource::Prompt => write!(f, "prompt"),
+            PassphraseSource::Env(var) => write!(f, "env:{}", var),
+            PassphraseSource::File(path) => write!(f, "file:{}", path),
+        }
+    }
+}
+
+///	Matches a string of the passphrase source value to the correct PassphraseSource
+/// # Arguments
+/// * `value` - The value to match
+/// # Returns
+/// The PassphraseSource, else the string that was not matched
+fn match_passphrase_source(value: &str) -> Result<PassphraseSource, &str> {
+    match value {
+        "prompt" => Ok(PassphraseSource::Prompt),
+        v if v.starts_with("env:") && v.len() > 4 => Ok(PassphraseSource::Env(v[4..].to_owned())),
+        v if v.starts_with("file:") && v.len() > 5 => Ok(PassphraseSource::File(v[5..].to_owned())),
+        _ => Err(value),
+    }
+}
+
+/// The LUKS2 container a partition gets encrypted with
 #[derive(Deserialize, Serialize, Debug)]
+pub struct EncryptConf {
+    /// The name of the mapping, the filesystem lives on /dev/mapper/<name>
+    pub name: String,
+    /// The cipher to use, the default of cryptsetup if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cipher: Option<String>,
+    /// The key derivation function to use, the default of cryptsetup if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pbkdf: Option<String>,
+    /// Where the passphrase comes from, a prompt if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passphrase: Option<PassphraseSource>,
+    /// The path of an additional key in the target system, used in the crypttab
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyfile: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
 pub struct PartConf {
-    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing)]
     pub index: usize,
     pub action: PartAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub size: Option<PartSize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub fs: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub fsargs: Option<String>,
+    /// The label of a swap partition
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The UUID of a swap partition, not to be confused with the GPT partition UUID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fsuuid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub mount: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub fstab: Option<bool>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub part_type: Option<PartType>,
+    /// The GPT partition name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The GPT partition UUID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flags: Option<Vec<PartFlag>>,
+    /// The kind of an msdos partition, primary if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<PartKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypt: Option<EncryptConf>,
 
     #[serde(skip_serializing)]
-    #[serde(skip_deserializing)]
     pub mount_point: Option<Mount>,
+    /// If seed opened the mapping of the encrypted partition
+    #[serde(skip_serializing)]
+    pub mapping_open: bool,
+}
+
+/// The fields of a PartConf as they appear in the installfile
+/// The path can be a field or the key of the partition ('- /dev/sda1: {...}')
+#[derive(Deserialize)]
+struct RawPartConf {
+    path: Option<String>,
+    action: Option<PartAction>,
+    size: Option<PartSize>,
+    fs: Option<String>,
+    fsargs: Option<String>,
+    label: Option<String>,
+    fsuuid: Option<String>,
+    mount: Option<String>,
+    fstab: Option<bool>,
+    #[serde(rename = "type")]
+    part_type: Option<PartType>,
+    name: Option<String>,
+    uuid: Option<String>,
+    flags: Option<Vec<PartFlag>>,
+    kind: Option<PartKind>,
+    encrypt: Option<EncryptConf>,
+
+    #[serde(flatten)]
+    keyed: HashMap<String, serde_json::Value>,
 }
 
 impl PartConf {
@@ -71,24 +343,88 @@ impl PartConf {
     fn context(&self) -> String {
         format!("Partition {}", self.index)
     }
+
+    /// Returns the kind of the partition, primary if unset
+    pub fn get_kind(&self) -> PartKind {
+        self.kind.unwrap_or(PartKind::Primary)
+    }
+
+    /// Returns if the partition holds swap space instead of a filesystem
+    pub fn is_swap(&self) -> bool {
+        self.fs.as_deref() == Some("swap")
+    }
+
+    /// Checks the encryption of the partition
+    /// # Arguments
+    /// * `encrypt` - The encryption config of the partition
+    fn validate_encrypt(&self, encrypt: &EncryptConf) -> Result<(), ValidationError> {
+        let index = self.index.to_string();
+        let error = |msg: &str| Err(ValidationError::new(index.as_str(), msg));
+
+        match self.action {
+            PartAction::Resize => return error("Encrypted partitions can't be resized"),
+            _ if self.get_kind() == PartKind::Extended => {
+                return error("Extended partitions can't be encrypted")
+            }
+            _ => (),
+        }
+
+        //The name becomes a device node in /dev/mapper
+        if encrypt.name.is_empty()
+            || encrypt.name == "control"
+            || encrypt
+                .name
+                .contains(|c: char| c == '/' || c.is_whitespace())
+        {
+            return error(format!("'{}' is not a valid mapping name", encrypt.name).as_str());
+        }
+
+        //Kept partitions already have their container, it only gets opened
+        if self.action == PartAction::Keep
+            && (encrypt.cipher.is_some() || encrypt.pbkdf.is_some() || encrypt.keyfile.is_some())
+        {
+            warn!(
+                "{} Ignoring 'cipher', 'pbkdf' and 'keyfile': Not allowed in this mode",
+                self.context()
+            );
+        }
+
+        //The keyfile gets written below the target root and listed in the crypttab
+        match &encrypt.keyfile {
+            Some(k)
+                if !k.starts_with('/')
+                    || k.contains(char::is_whitespace)
+                    || normalize_target_path(Path::new(k)).is_none_or(|p| p == Path::new("/")) =>
+            {
+                return error("'keyfile' needs to be an absolute file path in the target system")
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Returns if the partition table entry gets a type, name, UUID or flags
+    pub fn has_attributes(&self) -> bool {
+        self.part_type.is_some()
+            || self.name.is_some()
+            || self.uuid.is_some()
+            || self.flags.is_some()
+    }
 }
 
 impl Validate for PartConf {
     fn validate(&mut self) -> Result<(), ValidationError> {
         //Match the actions with their allowed entries
         match self.action {
-            //When keeping, the size can't be altered and the fs can't be changed
-            PartAction::Keep => {
-                if self.size.is_some() {
-                    warn!(
-                        "{} Ignoring 'size': Not allowed in this mode",
-                        self.context()
-                    );
-                }
-                if self.fs.is_some() {
-                    warn!("{} Ignoring 'fs': Not allowed in this mode", self.context());
-                }
-            }
+            //When keeping, the size and fs describe the existing partition: an exact size gets checked
+            PartAction::Keep => match self.size {
+                None | Some(PartSize::Size(..)) => (),
+                Some(_) => warn!(
+                    "{} Ignoring 'size': Only exact sizes are allowed in this mode",
+                    self.context()
+                ),
+            },
             //When formatting, changing the filesystem is allowed, but the size remains the same
             PartAction::Format => {
                 if self.size.is_some() {
@@ -135,8 +471,130 @@ impl Validate for PartConf {
             }
         }
 
-        //The fstab needs a mount point
-        if self.mount.is_none() {
+        //The table entry of a kept partition is left as it is
+        if self.action == PartAction::Keep && self.has_attributes() {
+            warn!(
+                "{} Ignoring 'type', 'name', 'uuid' and 'flags': Not allowed in this mode",
+                self.context()
+            );
+        }
+
+        //GPT names are limited to 36 characters
+        match &self.name {
+            Some(n) if n.encode_utf16().count() > 36 => {
+                return Err(ValidationError::new(
+                    self.index.to_string().as_str(),
+                    "'name' can't be longer than 36 characters",
+                ))
+            }
+            _ => (),
+        }
+
+        match &self.uuid {
+            Some(u) if !is_guid(u) => {
+                return Err(ValidationError::new(
+                    self.index.to_string().as_str(),
+                    "'uuid' needs to be a GUID (XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX)",
+                ))
+            }
+            _ => (),
+        }
+
+        //Extended partitions only hold the logical partitions
+        if self.get_kind() == PartKind::Extended {
+            if self.fs.is_some() || self.mount.is_some() {
+                return Err(ValidationError::new(
+                    self.index.to_string().as_str(),
+                    "Extended partitions can't have a 'fs' or 'mount'",
+                ));
+            }
+        }
+
+        //Check the size for values that can't be resolved
+        match &self.size {
+            Some(PartSize::Size(count, unit)) => {
+                if data_size_bytes(*count, unit).is_none() {
+                    return Err(ValidationError::new(
+                        self.index.to_string().as_str(),
+                        "The size exceeds the largest size a disk can have",
+                    ));
+                }
+            }
+            Some(PartSize::Total(PartTotal::Min)) => {
+                if self.action != PartAction::Resize {
+                    return Err(ValidationError::new(
+                        self.index.to_string().as_str(),
+                        "Size 'min' is only allowed when action is 'resize'",
+                    ));
+                }
+            }
+            Some(PartSize::PercentTotal(p)) | Some(PartSize::PercentFree(p)) => {
+                if *p <= 0.0 || *p > 1.0 {
+                    return Err(ValidationError::new(
+                        self.index.to_string().as_str(),
+                        "Percentage sizes need to be between 0 and 100",
+                    ));
+                }
+            }
+            _ => (),
+        }
+
+        match &self.encrypt {
+            Some(e) => self.validate_encrypt(e)?,
+            None => (),
+        }
+
+        //Swap space is enabled by the fstab, it has no mount point
+        if self.is_swap() && self.mount.is_some() {
+            return Err(ValidationError::new(
+                self.index.to_string().as_str(),
+                "Swap partitions can't be mounted, use 'fstab: true' to enable them at boot",
+            ));
+        }
+
+        //The mount point ends up in the fstab, where whitespace separates the fields
+        match &self.mount {
+            Some(m)
+                if !m.starts_with('/')
+                    || m.contains(char::is_whitespace)
+                    || normalize_target_path(Path::new(m)).is_none() =>
+            {
+                return Err(ValidationError::new(
+                    self.index.to_string().as_str(),
+                    format!("'{}' is not a mount point in the target system", m).as_str(),
+                ))
+            }
+            _ => (),
+        }
+        match &self.label {
+            Some(l) if l.is_empty() || l.contains(char::is_whitespace) => {
+                return Err(ValidationError::new(
+                    self.index.to_string().as_str(),
+                    "'label' can't be empty or contain whitespace",
+                ))
+            }
+            _ => (),
+        }
+
+        //mkswap is the only mkfs that gets a label and UUID from seed, the others use 'fsargs'
+        if !self.is_swap() && (self.label.is_some() || self.fsuuid.is_some()) {
+            return Err(ValidationError::new(
+                self.index.to_string().as_str(),
+                "'label' and 'fsuuid' are only supported with 'fs: swap'",
+            ));
+        }
+        match &self.fsuuid {
+            Some(u) if !is_guid(u) => {
+                return Err(ValidationError::new(
+                    self.index.to_string().as_str(),
+                    "'fsuuid' needs to be a UUID (XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX)",
+                ))
+            }
+            _ => (),
+        }
+
+        //The fstab needs a mount point, except for swap
+        if self.mount.is_none() && !self.is_swap() {
             match self.fstab {
                 Some(t) => {
                     if t {
@@ -154,6 +612,115 @@ impl Validate for PartConf {
     }
 }
 
+//
+//	A custom deserializer for PartConf
+//
+impl<'de> Deserialize<'de> for PartConf {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut raw = RawPartConf::deserialize(deserializer)?;
+
+        let keyed = std::mem::take(&mut raw.keyed);
+        let (path, nested) = match take_keyed_entry::<RawPartConf, D::Error>(keyed, "partition")? {
+            Some((key, nested)) => {
+                if raw.path.is_some() {
+                    return Err(de::Error::custom(format!(
+                        "Partition {} has a 'path' and a key",
+                        key
+                    )));
+                }
+                (Some(key), nested)
+            }
+            None => (raw.path.take(), None),
+        };
+
+        //Nested fields take precedence over the siblings of the path key
+        let raw = match nested {
+            Some(n) => RawPartConf {
+                path: None,
+                action: n.action.or(raw.action),
+                size: n.size.or(raw.size),
+                fs: n.fs.or(raw.fs),
+                fsargs: n.fsargs.or(raw.fsargs),
+                label: n.label.or(raw.label),
+                fsuuid: n.fsuuid.or(raw.fsuuid),
+                mount: n.mount.or(raw.mount),
+                fstab: n.fstab.or(raw.fstab),
+                part_type: n.part_type.or(raw.part_type),
+                name: n.name.or(raw.name),
+                uuid: n.uuid.or(raw.uuid),
+                flags: n.flags.or(raw.flags),
+                kind: n.kind.or(raw.kind),
+                encrypt: n.encrypt.or(raw.encrypt),
+                keyed: HashMap::new(),
+            },
+            None => raw,
+        };
+
+        Ok(PartConf {
+            action: match raw.action {
+                Some(a) => a,
+                None => {
+                    return Err(de::Error::custom(format!(
+                        "Partition {}: missing field `action`",
+                        path.as_deref().unwrap_or("")
+                    )))
+                }
+            },
+            //Gets determined by DiskConf::validate()
+            index: 0,
+            path,
+            size: raw.size,
+            fs: raw.fs,
+            fsargs: raw.fsargs,
+            label: raw.label,
+            fsuuid: raw.fsuuid,
+            mount: raw.mount,
+            fstab: raw.fstab,
+            part_type: raw.part_type,
+            name: raw.name,
+            uuid: raw.uuid,
+            flags: raw.flags,
+            kind: raw.kind,
+            encrypt: raw.encrypt,
+            mount_point: None,
+            mapping_open: false,
+        })
+    }
+}
+
+//
+//	A custom serializer for PartSize, writes the same syntax the deserializer reads
+//
+impl Serialize for PartSize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let value = match self {
+            PartSize::Size(count, unit) => format!(
+                "{}{}",
+                count,
+                match unit {
+                    DataSize::B => "B",
+                    DataSize::KB => "K",
+                    DataSize::MB => "M",
+                    DataSize::GB => "G",
+                    DataSize::TB => "T",
+                }
+            ),
+            //The deserializer only reads whole percentages
+            PartSize::PercentTotal(p) => format!("{}%", (p * 100.0).round()),
+            PartSize::PercentFree(p) => format!("{}%%", (p * 100.0).round()),
+            PartSize::Total(PartTotal::Min) => "min".to_owned(),
+            PartSize::Total(PartTotal::Max) => "max".to_owned(),
+        };
+        serializer.serialize_str(&value)
+    }
+}
+
 //
 //	A custom deserializer for PartSize
 //
@@ -178,7 +745,8 @@ impl<'de> Visitor<'de> for PartSizeVisitor {
     where
         E: de::Error,
     {
-        let (num_str, rest) = value.split_at(match value.find(|c: char| !c.is_numeric()) {
+        //Only ASCII digits, the other numerals (e.g. '½') can't be parsed
+        let (num_str, rest) = value.split_at(match value.find(|c: char| !c.is_ascii_digit()) {
             Some(s) => s,
             None => {
                 return Err(de::Error::custom(format!(
@@ -199,19 +767,21 @@ impl<'de> Visitor<'de> for PartSizeVisitor {
             }
         }
 
+        let percent = || match num_str.parse::<f32>() {
+            Ok(p) => Ok(p / 100.0),
+            Err(_) => Err(de::Error::custom(format!("Invalid percentage {}", value))),
+        };
+
         match rest {
-            "%" => {
-                return Ok(PartSize::PercentTotal(
-                    num_str.parse::<f32>().unwrap() / 100.0,
-                ))
-            }
-            "%%" => {
-                return Ok(PartSize::PercentFree(
-                    num_str.parse::<f32>().unwrap() / 100.0,
-                ))
-            }
+            "%" => return Ok(PartSize::PercentTotal(percent()?)),
+            "%%" => return Ok(PartSize::PercentFree(percent()?)),
             other => match match_data_size(other.to_lowercase().as_str()) {
-                Ok(s) => return Ok(PartSize::Size(num_str.parse().unwrap(), s)),
+                Ok(s) => match num_str.parse() {
+                    Ok(count) => return Ok(PartSize::Size(count, s)),
+                    Err(_) => {
+                        return Err(de::Error::custom(format!("Size {} is too large", value)))
+                    }
+                },
                 Err(e) => {
                     return Err(de::Error::custom(format!(
                         "Invalid variant of PartSize {}, expected one of {:?} or {:?}",
@@ -254,3 +824,184 @@ impl<'de> Visitor<'de> for PartActionVisitor {
         }
     }
 }
+
+//
+//	A custom serializer and deserializer for PartType
+//
+impl Serialize for PartType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for PartType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(PartTypeVisitor)
+    }
+}
+struct PartTypeVisitor;
+impl<'de> Visitor<'de> for PartTypeVisitor {
+    type Value = PartType;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a case-insensitive variant of PartType or a type GUID")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<PartType, E>
+    where
+        E: de::Error,
+    {
+        match match_part_type(value) {
+            Ok(s) => Ok(s),
+            Err(v) => Err(de::Error::custom(format!(
+                "Invalid variant of PartType {v}, expected one of {PART_TYPES:?}"
+            ))),
+        }
+    }
+}
+
+//
+//	A custom serializer and deserializer for PassphraseSource
+//
+impl Serialize for PassphraseSource {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for PassphraseSource {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(PassphraseSourceVisitor)
+    }
+}
+struct PassphraseSourceVisitor;
+impl<'de> Visitor<'de> for PassphraseSourceVisitor {
+    type Value = PassphraseSource;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a variant of PassphraseSource")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<PassphraseSource, E>
+    where
+        E: de::Error,
+    {
+        match match_passphrase_source(value) {
+            Ok(s) => Ok(s),
+            Err(v) => Err(de::Error::custom(format!(
+                "Invalid variant of PassphraseSource {v}, expected one of {PASSPHRASE_SOURCES:?}"
+            ))),
+        }
+    }
+}
+
+//
+//	A custom deserializer for PartFlag
+//
+impl<'de> Deserialize<'de> for PartFlag {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(PartFlagVisitor)
+    }
+}
+struct PartFlagVisitor;
+impl<'de> Visitor<'de> for PartFlagVisitor {
+    type Value = PartFlag;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a case-insensitive variant of PartFlag")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<PartFlag, E>
+    where
+        E: de::Error,
+    {
+        match match_part_flag(value.to_lowercase().as_str()) {
+            Ok(s) => Ok(s),
+            Err(v) => Err(de::Error::custom(format!(
+                "Invalid variant of PartFlag {v}, expected one of {PART_FLAGS:?}"
+            ))),
+        }
+    }
+}
+
+//
+//	A custom deserializer for PartKind
+//
+impl<'de> Deserialize<'de> for PartKind {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(PartKindVisitor)
+    }
+}
+struct PartKindVisitor;
+impl<'de> Visitor<'de> for PartKindVisitor {
+    type Value = PartKind;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a case-insensitive variant of PartKind")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<PartKind, E>
+    where
+        E: de::Error,
+    {
+        match match_part_kind(value.to_lowercase().as_str()) {
+            Ok(s) => Ok(s),
+            Err(v) => Err(de::Error::custom(format!(
+                "Invalid variant of PartKind {v}, expected one of {PART_KINDS:?}"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses the size as it would appear in an installfile
+    fn parse_size(size: &str) -> Result<PartSize, serde_json::Error> {
+        serde_json::from_value(serde_json::Value::String(size.to_owned()))
+    }
+
+    #[test]
+    fn guids_need_five_hex_groups() {
+        assert!(is_guid("C12A7328-F81F-11D2-BA4B-00A0C93EC93B"));
+        assert!(is_guid("0fc63daf-8483-4772-8e79-3d69d8477de4"));
+        assert!(!is_guid("C12A7328-F81F-11D2-BA4B-00A0C93EC93"));
+        assert!(!is_guid("C12A7328F81F-11D2-BA4B-00A0C93EC93B"));
+        assert!(!is_guid("G12A7328-F81F-11D2-BA4B-00A0C93EC93B"));
+        assert!(!is_guid("C12A7328-F81F-11D2-BA4B-00A0C93EC93B-"));
+        assert!(!is_guid(""));
+    }
+
+    #[test]
+    fn percentages_get_parsed() {
+        assert!(matches!(parse_size("50%"), Ok(PartSize::PercentTotal(p)) if p == 0.5));
+        assert!(matches!(parse_size("25%%"), Ok(PartSize::PercentFree(p)) if p == 0.25));
+    }
+
+    #[test]
+    fn non_ascii_numerals_are_an_error() {
+        assert!(parse_size("½%").is_err());
+        assert!(parse_size("½%%").is_err());
+        assert!(parse_size("٣G").is_err());
+        assert!(parse_size("1½%").is_err());
+    }
+}
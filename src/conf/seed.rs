@@ -1,11 +1,17 @@
 pub use super::disk::*;
 pub use super::validate::*;
+use crate::event::EventSink;
+use crate::journal::Journal;
+use crate::plan::Plan;
 use serde::de;
 use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 
 #[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum FSTabMode {
     UUID,
     Label,
@@ -14,6 +20,17 @@ pub enum FSTabMode {
 pub const FSTAB_MODES: &'static [&'static str] = &["uuid", "label", "device"];
 //When changing this, remember to change match_fstab_mode()
 
+/// What to do if a symlink should be created where a file already exists
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    Fail,
+    Replace,
+    Backup,
+}
+pub const SYMLINK_POLICIES: &'static [&'static str] = &["fail", "replace", "backup"];
+//When changing this, remember to change match_symlink_policy()
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct EnvConf {
     pub chrootcmd: Option<String>,
@@ -34,6 +51,7 @@ pub struct FSTabConf {
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct SystemdConf {
+    #[serde(rename = "enable-units", alias = "enable_units")]
     pub enable_units: Option<Vec<String>>,
 }
 
@@ -48,9 +66,21 @@ pub struct LangConf {
     pub locales: Option<Vec<String>>,
 }
 
+/// A swapfile on the root of the target system
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SwapfileConf {
+    /// The path inside the target system, '/swapfile' if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub size: PartSize,
+    /// If the swapfile should enter the fstab, true if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fstab: Option<bool>,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct SeedConf {
-    #[serde(default = "seed_default_workdir")]
+    #[serde(default = "seed_default_workdir", skip_serializing)]
     pub workdir: String,
 
     pub env: EnvConf,
@@ -59,8 +89,28 @@ pub struct SeedConf {
     pub fstab: FSTabConf,
     pub systemd: SystemdConf,
     pub symlinks: Option<Vec<HashMap<String, String>>>,
+    #[serde(rename = "symlink-policy", alias = "symlink_policy")]
+    pub symlink_policy: Option<SymlinkPolicy>,
     pub time: Option<TimeConf>,
     pub lang: Option<LangConf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swapfile: Option<SwapfileConf>,
+
+    /// Only plan the changes, do not touch the system
+    #[serde(skip)]
+    pub dry_run: bool,
+    /// The changes that got performed or would be performed
+    #[serde(skip)]
+    pub plan: Plan,
+    /// The record of the changes to the system, to undo them on failure
+    #[serde(skip)]
+    pub journal: Journal,
+    /// The progress events for frontends
+    #[serde(skip)]
+    pub events: EventSink,
+    /// Set to stop the installation before the next stage
+    #[serde(skip)]
+    pub cancel: Arc<AtomicBool>,
 }
 
 impl Validate for SeedConf {
@@ -70,6 +120,41 @@ impl Validate for SeedConf {
             disk.validate()?;
         }
 
+        //Every mapping of an encrypted partition needs its own name
+        let mut mappings: Vec<&str> = Vec::new();
+        for disk in &self.disks {
+            for part in &disk.partitions {
+                let name = match &part.encrypt {
+                    Some(e) => e.name.as_str(),
+                    None => continue,
+                };
+                if mappings.contains(&name) {
+                    let context = format!("{} -> {}", disk.path, part.index);
+                    return Err(ValidationError::new(
+                        context.as_str(),
+                        format!("The mapping name '{}' is used multiple times", name).as_str(),
+                    ));
+                }
+                mappings.push(name);
+            }
+        }
+
+        //Check the timezone and locales, if the data to check against is available
+        match &self.time {
+            Some(time) => crate::target::time::validate_timezone(&self.workdir, time)?,
+            None => (),
+        }
+        match &self.lang {
+            Some(lang) => crate::target::locale::validate_locales(&self.workdir, lang)?,
+            None => (),
+        }
+        match &self.swapfile {
+            Some(swapfile) => crate::target::swapfile::validate_swapfile(swapfile)?,
+            None => (),
+        }
+        crate::target::fstab::validate_fstab(&self.fstab)?;
+        crate::target::systemd::validate_units(&self.systemd)?;
+
         Ok(())
     }
 }
@@ -80,6 +165,17 @@ impl Drop for SeedConf {
             Ok(_) => (),
             Err(e) => {
                 error!("Failed to unmount remaining partitions! The system may be in a uncontrolled state! (error: {})", e.to_string());
+                return;
+            }
+        }
+        //The mappings can only be closed once nothing uses them
+        match self.close_mappings() {
+            Ok(_) => (),
+            Err(e) => {
+                error!(
+                    "Failed to close the encrypted partitions! (error: {})",
+                    e.to_string()
+                );
             }
         }
     }
@@ -99,6 +195,20 @@ fn match_fstab_mode(value: &str) -> Result<FSTabMode, &str> {
     }
 }
 
+///	Matches a string of the symlink policy value to the correct SymlinkPolicy
+/// # Arguments
+/// * `value` - The value to match
+/// # Returns
+/// The SymlinkPolicy, else the string that was not matched
+fn match_symlink_policy(value: &str) -> Result<SymlinkPolicy, &str> {
+    match value {
+        "fail" => Ok(SymlinkPolicy::Fail),
+        "replace" => Ok(SymlinkPolicy::Replace),
+        "backup" => Ok(SymlinkPolicy::Backup),
+        _ => Err(value),
+    }
+}
+
 /// Returns the default value for the working directory
 /// # Returns
 /// Default workdir for seed: "./seed_workdir/"
@@ -137,3 +247,35 @@ impl<'de> de::Visitor<'de> for FSTabModeVisitor {
         }
     }
 }
+
+//
+//	A custom deserializer for SymlinkPolicy
+//
+impl<'de> Deserialize<'de> for SymlinkPolicy {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(SymlinkPolicyVisitor)
+    }
+}
+struct SymlinkPolicyVisitor;
+impl<'de> de::Visitor<'de> for SymlinkPolicyVisitor {
+    type Value = SymlinkPolicy;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a case-insensitive variant of SymlinkPolicy")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<SymlinkPolicy, E>
+    where
+        E: de::Error,
+    {
+        match match_symlink_policy(value.to_lowercase().as_str()) {
+            Ok(s) => Ok(s),
+            Err(v) => Err(de::Error::custom(format!(
+                "Invalid variant of SymlinkPolicy {v}, expected one of {SYMLINK_POLICIES:?}"
+            ))),
+        }
+    }
+}
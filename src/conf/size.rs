@@ -10,7 +10,7 @@ pub enum DataSize {
     GB,
     TB,
 }
-pub const DATA_SIZES: &'static [&'static str] = &["B", "KB", "MB", "GB", "TB"];
+pub const DATA_SIZES: &'static [&'static str] = &["B", "K(B)", "M(B)", "G(B)", "T(B)"];
 
 ///	Matches a string of the data size value to the correct DataSize
 /// # Arguments
@@ -20,29 +20,61 @@ pub const DATA_SIZES: &'static [&'static str] = &["B", "KB", "MB", "GB", "TB"];
 pub fn match_data_size(value: &str) -> Result<DataSize, &str> {
     match value {
         "b" => Ok(DataSize::B),
-        "kb" => Ok(DataSize::KB),
-        "mb" => Ok(DataSize::MB),
-        "gb" => Ok(DataSize::GB),
-        "tb" => Ok(DataSize::TB),
+        "k" | "kb" => Ok(DataSize::KB),
+        "m" | "mb" => Ok(DataSize::MB),
+        "g" | "gb" => Ok(DataSize::GB),
+        "t" | "tb" => Ok(DataSize::TB),
         _ => Err(value),
     }
 }
 
-pub fn data_size_bytes(count: i64, unit: &DataSize) -> i64 {
-    match unit {
-        DataSize::B => count,
-        DataSize::KB => count * 1024,
-        DataSize::MB => count * 1024 * 1024,
-        DataSize::GB => count * 1024 * 1024 * 1024,
-        DataSize::TB => count * 1024 * 1024 * 1024 * 1024,
-    }
+/// Converts a count of the provided unit to bytes
+/// # Arguments
+/// * `count` - The count of the unit
+/// * `unit` - The unit
+/// # Returns
+/// The bytes, None if they exceed the largest size a disk can address (i64::MAX)
+pub fn data_size_bytes(count: u64, unit: &DataSize) -> Option<u64> {
+    let unit_bytes: u64 = match unit {
+        DataSize::B => 1,
+        DataSize::KB => 1024,
+        DataSize::MB => 1024 * 1024,
+        DataSize::GB => 1024 * 1024 * 1024,
+        DataSize::TB => 1024 * 1024 * 1024 * 1024,
+    };
+
+    count
+        .checked_mul(unit_bytes)
+        .filter(|b| *b <= i64::MAX as u64)
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Debug)]
 pub enum PartSize {
-    Size(u32, DataSize),
+    Size(u64, DataSize),
     PercentTotal(f32),
     PercentFree(f32),
     Total(PartTotal),
 }
 pub const PART_SIZES: &'static [&'static str] = &["%", "%%", "min/max..."];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_size_bytes_converts_units() {
+        assert_eq!(data_size_bytes(3, &DataSize::B), Some(3));
+        assert_eq!(data_size_bytes(2, &DataSize::MB), Some(2 * 1024 * 1024));
+        assert_eq!(data_size_bytes(1, &DataSize::TB), Some(1 << 40));
+    }
+
+    #[test]
+    fn data_size_bytes_rejects_overflow() {
+        assert_eq!(data_size_bytes(u64::MAX, &DataSize::KB), None);
+        assert_eq!(data_size_bytes(1 << 23, &DataSize::TB), None);
+        assert_eq!(
+            data_size_bytes((1 << 23) - 1, &DataSize::TB),
+            Some(((1 << 23) - 1) << 40)
+        );
+    }
+}
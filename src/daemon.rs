@@ -0,0 +1,567 @@
+use crate::conf::installfile::*;
+use crate::conf::validate::ValidationError;
+use crate::diskmgr::{probe, safety};
+use crate::error::SeedError;
+use crate::event::{Event, EventSink};
+use crate::install;
+use crate::journal::Journal;
+use crate::target::symlinks::normalize_target_path;
+use crate::target::{fstab, swapfile};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// How many lines a subscriber may fall behind before it gets dropped
+const SUBSCRIBER_BACKLOG: usize = 1024;
+
+/// A request of a client, sent as a single JSON line
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Request {
+    /// Submits the contents of an installfile (JSON or YAML)
+    Submit { installfile: String },
+    /// Validates the submitted installfile
+    Validate,
+    /// Lists the disks of the system (like 'seed probe')
+    Disks,
+    /// Starts installing the submitted installfile
+    Start {
+        #[serde(default)]
+        yes_destroy: bool,
+    },
+    /// Stops the running installation before its next stage
+    Cancel,
+    /// Returns the state of the daemon
+    Status,
+    /// Streams the progress events of installations over this connection
+    Subscribe,
+}
+
+/// The response to a request, sent as a single JSON line
+#[derive(Serialize, Debug)]
+struct Response {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+impl Response {
+    /// Creates a successful response
+    /// # Arguments
+    /// * `data` - The data to respond with, if any
+    fn ok(data: Option<Value>) -> Response {
+        Response {
+            ok: true,
+            error: None,
+            data,
+        }
+    }
+
+    /// Creates a failed response
+    /// # Arguments
+    /// * `error` - What went wrong
+    fn error<T: ToString>(error: T) -> Response {
+        Response {
+            ok: false,
+            error: Some(error.to_string()),
+            data: None,
+        }
+    }
+}
+
+/// The queues of the connections that subscribed to progress events
+/// Every subscriber has its own thread writing its queue, a slow client does not block the installation
+/// Writes go to every queue, subscribers that went away get dropped, the ones that fell behind get disconnected
+#[derive(Clone, Default)]
+struct Subscribers(Arc<Mutex<Vec<Subscriber>>>);
+
+/// A connection that subscribed to progress events
+struct Subscriber {
+    /// The queue the writing thread of the connection reads from
+    queue: SyncSender<Vec<u8>>,
+    /// The connection, to disconnect the subscriber
+    stream: UnixStream,
+}
+
+impl Subscribers {
+    /// Subscribes a connection to the events and starts the thread writing them
+    /// # Arguments
+    /// * `stream` - The connection of the client
+    /// * `response` - The response to the subscription, it gets written before the events
+    /// # Returns
+    /// The queue of the connection, responses need to go through it to not mix with the events
+    fn subscribe(&self, stream: UnixStream, response: Vec<u8>) -> SyncSender<Vec<u8>> {
+        let (sender, receiver) = sync_channel::<Vec<u8>>(SUBSCRIBER_BACKLOG);
+        let connection = stream.try_clone();
+        std::thread::spawn(move || {
+            for buf in receiver {
+                if (&stream).write_all(&buf).is_err() {
+                    break;
+                }
+            }
+        });
+
+        //The queue is empty, this can not fail
+        let _ = sender.try_send(response);
+        match connection {
+            Ok(c) => lock(&self.0).push(Subscriber {
+                queue: sender.clone(),
+                stream: c,
+            }),
+            Err(e) => warn!("Failed to subscribe client: {}", e),
+        }
+        sender
+    }
+}
+
+impl Write for Subscribers {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        lock(&self.0).retain(|s| match s.queue.try_send(buf.to_vec()) {
+            Ok(_) => true,
+            Err(TrySendError::Full(_)) => {
+                warn!("Disconnecting a subscriber that fell behind");
+                let _ = s.stream.shutdown(Shutdown::Both);
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The state shared between the connections of the daemon
+#[derive(Default)]
+struct DaemonState {
+    /// The last submitted installfile
+    submitted: Option<InstallFile>,
+    /// If an installation is running
+    running: bool,
+    /// The error of the last installation, if it failed
+    last_error: Option<String>,
+    /// Set to cancel the running installation
+    cancel: Arc<AtomicBool>,
+}
+
+/// The daemon, shared between the threads serving the connections
+struct Daemon {
+    workdir: String,
+    state: Mutex<DaemonState>,
+    subscribers: Subscribers,
+}
+
+/// Locks the mutex, a panicked connection thread does not take the daemon down
+/// # Arguments
+/// * `mutex` - The mutex to lock
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(g) => g,
+        Err(e) => e.into_inner(),
+    }
+}
+
+/// Prepares the installfile for the daemon and installs it
+/// # Arguments
+/// * `daemon` - The daemon
+/// * `conf` - The installfile to install
+/// * `yes_destroy` - Do not ask for confirmation before destroying data
+fn install_submitted(
+    daemon: &Daemon,
+    conf: &mut InstallFile,
+    yes_destroy: bool,
+) -> Result<(), SeedError> {
+    conf.seed.events = EventSink::from_writer(Box::new(daemon.subscribers.clone()));
+    conf.seed.journal = Journal::new(&daemon.workdir);
+    conf.seed.workdir = daemon.workdir.clone();
+    conf.seed.cancel = lock(&daemon.state).cancel.clone();
+
+    conf.validate()?;
+    //The daemon has no terminal to ask on, the request has to confirm it
+    safety::check_disks(&conf.seed, yes_destroy, false)?;
+    install::install(&mut conf.seed)
+}
+
+/// Checks a submitted installfile for settings clients of the daemon may not use
+/// They would let a client run commands, read files or write outside the target as the user of the daemon
+/// # Arguments
+/// * `conf` - The submitted installfile
+fn check_submitted(conf: &InstallFile) -> Result<(), String> {
+    if conf.seed.env.chrootcmd.is_some() {
+        return Err(
+            "'env.chrootcmd' is not allowed for installfiles submitted to the daemon".to_owned(),
+        );
+    }
+
+    //The files seed writes have to end up inside the target system
+    fstab::validate_fstab(&conf.seed.fstab).map_err(|e| e.to_string())?;
+    match &conf.seed.swapfile {
+        Some(swapfile) => swapfile::validate_swapfile(swapfile).map_err(|e| e.to_string())?,
+        None => (),
+    }
+
+    for part in conf.seed.disks.iter().flat_map(|d| d.partitions.iter()) {
+        let keyfile = part.encrypt.as_ref().and_then(|e| e.keyfile.as_ref());
+        for path in part.mount.iter().chain(keyfile) {
+            if !path.starts_with('/') || normalize_target_path(Path::new(path)).is_none() {
+                return Err(format!(
+                    "Partition {}: '{}' is not a path in the target system",
+                    part.index, path
+                ));
+            }
+        }
+
+        //The daemon has no terminal to prompt on and may not read files of its user
+        match &part.encrypt {
+            Some(e) => match e.passphrase {
+                Some(PassphraseSource::Env(_)) => (),
+                _ => {
+                    return Err(format!(
+                        "{}: Only 'env:' passphrases are allowed for installfiles submitted to the daemon",
+                        e.name
+                    ))
+                }
+            },
+            None => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs an installation of the installfile and records the result in the daemon state
+/// # Arguments
+/// * `daemon` - The daemon
+/// * `conf` - The installfile to install
+/// * `yes_destroy` - Do not ask for confirmation before destroying data
+fn run_install(daemon: &Daemon, mut conf: InstallFile, yes_destroy: bool) {
+    let res = install_submitted(daemon, &mut conf, yes_destroy);
+
+    //Unmount everything before reporting the installation as finished
+    let events = std::mem::take(&mut conf.seed.events);
+    drop(conf);
+
+    let error = match res {
+        Ok(_) => {
+            info!("Installed system");
+            None
+        }
+        Err(e) => {
+            error!("{}", e.to_string());
+            Some(e.to_string())
+        }
+    };
+    {
+        let mut state = lock(&daemon.state);
+        state.running = false;
+        state.last_error = error.clone();
+    }
+    events.emit(Event::InstallFinished { error });
+}
+
+/// Handles a request of a client, subscriptions get set up by serve_client()
+/// # Arguments
+/// * `daemon` - The daemon
+/// * `request` - The request to handle
+fn handle_request(daemon: &Arc<Daemon>, request: Request) -> Response {
+    match request {
+        Request::Submit { installfile } => {
+            let format = InstallFileFormat::detect("", &installfile);
+            let conf = match InstallFile::parse("<submitted>", &installfile, &format) {
+                Ok(c) => c,
+                Err(e) => return Response::error(e),
+            };
+            match check_submitted(&conf) {
+                Ok(_) => {
+                    lock(&daemon.state).submitted = Some(conf);
+                    Response::ok(None)
+                }
+                Err(e) => Response::error(e),
+            }
+        }
+        Request::Validate => {
+            let mut state = lock(&daemon.state);
+            match state.submitted.as_mut() {
+                Some(conf) => {
+                    conf.seed.workdir = daemon.workdir.clone();
+                    match conf.validate() {
+                        Ok(_) => Response::ok(None),
+                        Err(e) => Response::error(e),
+                    }
+                }
+                None => Response::error("No installfile has been submitted"),
+            }
+        }
+        Request::Disks => match serde_json::to_value(probe::probe_disks()) {
+            Ok(disks) => Response::ok(Some(disks)),
+            Err(e) => Response::error(e),
+        },
+        Request::Start { yes_destroy } => {
+            let mut state = lock(&daemon.state);
+            if state.running {
+                return Response::error("An installation is already running");
+            }
+            let conf = match state.submitted.take() {
+                Some(c) => c,
+                None => return Response::error("No installfile has been submitted"),
+            };
+            state.running = true;
+            state.last_error = None;
+            state.cancel.store(false, Ordering::SeqCst);
+
+            let daemon = daemon.clone();
+            std::thread::spawn(move || run_install(&daemon, conf, yes_destroy));
+            Response::ok(None)
+        }
+        Request::Cancel => {
+            let state = lock(&daemon.state);
+            if !state.running {
+                return Response::error("No installation is running");
+            }
+            state.cancel.store(true, Ordering::SeqCst);
+            Response::ok(None)
+        }
+        Request::Status => {
+            let state = lock(&daemon.state);
+            let status = match (state.running, state.submitted.is_some()) {
+                (true, _) => "running",
+                (false, true) => "submitted",
+                (false, false) => "idle",
+            };
+            Response::ok(Some(json!({
+                "status": status,
+                "last_error": state.last_error,
+            })))
+        }
+        Request::Subscribe => Response::ok(None),
+    }
+}
+
+/// Serves the requests of a client until it disconnects
+/// # Arguments
+/// * `daemon` - The daemon
+/// * `stream` - The connection of the client
+fn serve_client(daemon: Arc<Daemon>, stream: UnixStream) {
+    let reader = match stream.try_clone() {
+        Ok(s) => BufReader::new(s),
+        Err(e) => {
+            warn!("Failed to serve client: {}", e);
+            return;
+        }
+    };
+
+    //The queue of the connection once it subscribed
+    let mut subscription: Option<SyncSender<Vec<u8>>> = None;
+
+    for line in reader.lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(_) => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut subscribe = false;
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                debug!("Daemon request: {:?}", request);
+                subscribe = matches!(request, Request::Subscribe);
+                handle_request(&daemon, request)
+            }
+            Err(e) => Response::error(format!("Invalid request: {}", e)),
+        };
+
+        let line = match serde_json::to_string(&response) {
+            Ok(l) => l + "\n",
+            Err(e) => {
+                warn!("Failed to serialize response: {}", e);
+                continue;
+            }
+        };
+
+        //Responses and events share the connection of subscribers, they go through its queue
+        let sent = match &subscription {
+            Some(sender) => sender.try_send(line.into_bytes()).is_ok(),
+            None if subscribe => match stream.try_clone() {
+                Ok(s) => {
+                    subscription = Some(daemon.subscribers.subscribe(s, line.into_bytes()));
+                    true
+                }
+                Err(e) => {
+                    warn!("Failed to subscribe client: {}", e);
+                    false
+                }
+            },
+            None => (&stream).write_all(line.as_bytes()).is_ok(),
+        };
+        if !sent {
+            break;
+        }
+    }
+}
+
+/// Runs the daemon, serving clients on the Unix socket until seed gets killed
+/// # Arguments
+/// * `socket` - The path of the socket to listen on
+/// * `socket_mode` - The permissions of the socket as an octal string (e.g. '660')
+/// * `workdir` - The directory seed should mount its root
+pub fn run_daemon(socket: &str, socket_mode: &str, workdir: &str) -> Result<(), SeedError> {
+    let mode = u32::from_str_radix(socket_mode, 8).map_err(|_| {
+        ValidationError::new(
+            "socket-mode",
+            format!("'{}' is not an octal mode", socket_mode).as_str(),
+        )
+    })?;
+
+    //A socket left over from an earlier daemon would make binding fail, nothing else gets removed
+    if let Ok(metadata) = std::fs::symlink_metadata(socket) {
+        let in_use = |msg: &str| {
+            SeedError::io(
+                &format!("Failed to bind {}", socket),
+                std::io::Error::new(std::io::ErrorKind::AddrInUse, msg),
+            )
+        };
+        if !metadata.file_type().is_socket() {
+            return Err(in_use("It exists and is not a socket"));
+        }
+        match UnixStream::connect(socket) {
+            Ok(_) => return Err(in_use("Another daemon is listening on it")),
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                debug!("Removing the stale socket {}", socket);
+                std::fs::remove_file(socket)
+                    .map_err(|e| SeedError::io(&format!("Failed to remove {}", socket), e))?;
+            }
+            Err(e) => return Err(SeedError::io(&format!("Failed to bind {}", socket), e)),
+        }
+    }
+    let listener = UnixListener::bind(socket)
+        .map_err(|e| SeedError::io(&format!("Failed to bind {}", socket), e))?;
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(mode))
+        .map_err(|e| SeedError::io(&format!("Failed to set the permissions of {}", socket), e))?;
+    info!("Listening on {}", socket);
+
+    let daemon = Arc::new(Daemon {
+        workdir: workdir.to_owned(),
+        state: Mutex::new(DaemonState::default()),
+        subscribers: Subscribers::default(),
+    });
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let daemon = daemon.clone();
+                std::thread::spawn(move || serve_client(daemon, stream));
+            }
+            Err(e) => warn!("Failed to accept client: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::fixture;
+
+    /// Parses the installfile with the provided settings
+    fn parse(chrootcmd: &str, passphrase: &str) -> InstallFile {
+        parse_paths(chrootcmd, passphrase, "/", "/etc/keys/root.key")
+    }
+
+    /// Parses the installfile with the provided settings and target paths
+    fn parse_paths(chrootcmd: &str, passphrase: &str, mount: &str, keyfile: &str) -> InstallFile {
+        fixture::parse(&format!(
+            r#"
+env:
+  chrootcmd: {}
+disks:
+  - /dev/sdx:
+    action: new
+    table: gpt
+    partitions:
+      - action: create
+        size: max
+        fs: ext4
+        mount: {}
+        encrypt:
+          name: cryptroot
+          passphrase: {}
+          keyfile: {}
+"#,
+            chrootcmd, mount, passphrase, keyfile
+        ))
+    }
+
+    #[test]
+    fn submitted_installfile_can_not_run_commands_or_read_files() {
+        assert!(check_submitted(&parse("~", "env:SEED_PASSPHRASE")).is_ok());
+        assert!(check_submitted(&parse("sh -c '{COMMAND}'", "env:SEED_PASSPHRASE")).is_err());
+        assert!(check_submitted(&parse("~", "file:/etc/shadow")).is_err());
+    }
+
+    #[test]
+    fn submitted_installfile_can_not_prompt() {
+        assert!(check_submitted(&parse("~", "prompt")).is_err());
+        assert!(check_submitted(&parse("~", "~")).is_err());
+    }
+
+    #[test]
+    fn submitted_installfile_can_not_write_outside_the_target() {
+        let passphrase = "env:SEED_PASSPHRASE";
+        assert!(check_submitted(&parse_paths("~", passphrase, "/../..", "/root.key")).is_err());
+        assert!(check_submitted(&parse_paths("~", passphrase, "/", "/../etc/shadow")).is_err());
+        assert!(check_submitted(&parse_paths("~", passphrase, "/", "etc/root.key")).is_err());
+
+        let mut conf = parse("~", passphrase);
+        conf.seed.fstab.path = Some("/../../etc/fstab".to_owned());
+        assert!(check_submitted(&conf).is_err());
+
+        let conf = fixture::parse("swapfile:\n  path: /../../etc/passwd\n  size: 1G\n");
+        assert!(check_submitted(&conf).is_err());
+    }
+
+    #[test]
+    fn subscriber_gets_response_before_events() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let mut subscribers = Subscribers::default();
+        subscribers.subscribe(server, b"response\n".to_vec());
+        subscribers.write_all(b"event\n").unwrap();
+
+        let mut lines = BufReader::new(client).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "response");
+        assert_eq!(lines.next().unwrap().unwrap(), "event");
+    }
+
+    #[test]
+    fn stalled_subscriber_gets_dropped() {
+        //The client never reads, the socket buffer and then the queue fill up
+        let (_client, server) = UnixStream::pair().unwrap();
+        let mut subscribers = Subscribers::default();
+        subscribers.subscribe(server, Vec::new());
+
+        let line = [b'x'; 4096];
+        for _ in 0..SUBSCRIBER_BACKLOG * 2 {
+            subscribers.write_all(&line).unwrap();
+        }
+        assert!(lock(&subscribers.0).is_empty());
+    }
+
+    #[test]
+    fn invalid_socket_mode_is_invalid_config() {
+        let res = run_daemon("/nonexistent/seed.sock", "rw-rw----", "/nonexistent");
+        assert!(matches!(res, Err(SeedError::Validation(_))));
+    }
+}
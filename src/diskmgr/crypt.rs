@@ -0,0 +1,262 @@
+use crate::conf::part::*;
+use crate::conf::seed::SeedConf;
+use crate::conf::validate::ValidationError;
+use crate::error::SeedError;
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The size of the keys seed generates for 'keyfile'
+const KEYFILE_BYTES: usize = 512;
+
+/// Returns the path the generated key of an encrypted partition is kept at until it gets copied to the target
+/// # Arguments
+/// * `workdir` - The working directory of seed
+/// * `encrypt` - The encryption config of the partition
+pub fn get_keyfile_host_path(workdir: &str, encrypt: &EncryptConf) -> PathBuf {
+    Path::new(workdir)
+        .join("keys")
+        .join(format!("{}.key", encrypt.name))
+}
+
+/// Reads the passphrase of an encrypted partition from its source
+/// # Arguments
+/// * `device` - The encrypted partition (for the prompt)
+/// * `encrypt` - The encryption config of the partition
+/// * `confirm` - If a prompted passphrase needs to be entered twice
+pub fn read_passphrase(
+    device: &str,
+    encrypt: &EncryptConf,
+    confirm: bool,
+) -> Result<String, SeedError> {
+    let context = format!("Reading the passphrase of {}", encrypt.name);
+    //The source does not provide a passphrase, the installfile needs to be fixed
+    let invalid = |msg: &str| -> SeedError { ValidationError::new(&encrypt.name, msg).into() };
+
+    let passphrase = match encrypt
+        .passphrase
+        .as_ref()
+        .unwrap_or(&PassphraseSource::Prompt)
+    {
+        PassphraseSource::Prompt => {
+            let prompt = format!("Passphrase for {} ({}): ", encrypt.name, device);
+            let passphrase =
+                crate::libc::read_password(&prompt).map_err(|e| SeedError::io(&context, e))?;
+            if confirm {
+                let again = crate::libc::read_password("Repeat the passphrase: ")
+                    .map_err(|e| SeedError::io(&context, e))?;
+                if again != passphrase {
+                    return Err(invalid("The passphrases do not match"));
+                }
+            }
+            passphrase
+        }
+        PassphraseSource::Env(var) => match std::env::var(var) {
+            Ok(p) => p,
+            Err(_) => return Err(invalid(format!("{} is not set", var).as_str())),
+        },
+        //A line break at the end of the file is not part of the passphrase, it is not typed at boot
+        PassphraseSource::File(path) => std::fs::read_to_string(path)
+            .map_err(|e| invalid(format!("Failed to read {}: {}", path, e).as_str()))?
+            .trim_end_matches(['\r', '\n'])
+            .to_owned(),
+    };
+
+    match passphrase.is_empty() {
+        true => Err(invalid("The passphrase is empty")),
+        false => Ok(passphrase),
+    }
+}
+
+/// Returns the cryptsetup command line that creates the LUKS2 container, it reads the passphrase from stdin
+/// # Arguments
+/// * `device` - The partition to encrypt
+/// * `encrypt` - The encryption config of the partition
+pub fn get_luks_format_command(device: &str, encrypt: &EncryptConf) -> Vec<String> {
+    let mut command: Vec<String> = vec![
+        "cryptsetup".to_owned(),
+        "luksFormat".to_owned(),
+        "--batch-mode".to_owned(),
+        "--type=luks2".to_owned(),
+    ];
+
+    match &encrypt.cipher {
+        Some(c) => command.push(format!("--cipher={}", c)),
+        None => (),
+    }
+    match &encrypt.pbkdf {
+        Some(p) => command.push(format!("--pbkdf={}", p)),
+        None => (),
+    }
+
+    command.push("--key-file=-".to_owned());
+    command.push(device.to_owned());
+
+    command
+}
+
+/// Runs cryptsetup, the passphrase gets passed on stdin and never shows up in the arguments
+/// # Arguments
+/// * `args` - The command line, starting with 'cryptsetup'
+/// * `passphrase` - The passphrase to pass
+fn run_cryptsetup(args: &[String], passphrase: &str) -> Result<(), SeedError> {
+    let what = args.join(" ");
+    debug!("Running {}", what);
+
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| SeedError::command(&what, e))?;
+
+    //cryptsetup uses everything on stdin as the key, a line break would become part of it
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(passphrase.as_bytes())
+        .map_err(|e| SeedError::command(&what, e))?;
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| SeedError::command(&what, e))?;
+
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(SeedError::command(
+            &what,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        )),
+    }
+}
+
+/// Generates a random key for 'keyfile', only root can read it
+/// # Arguments
+/// * `path` - The path to write the key to
+fn generate_keyfile(path: &Path) -> Result<(), SeedError> {
+    let context = format!("Generating {}", path.to_string_lossy());
+
+    let mut key = [0u8; KEYFILE_BYTES];
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut key))
+        .map_err(|e| SeedError::io(&context, e))?;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .and_then(|_| std::fs::set_permissions(parent, std::fs::Permissions::from_mode(0o700)))
+            .map_err(|e| SeedError::io(&context, e))?;
+    }
+    //A key of a previous attempt belongs to a container that is gone
+    if path.exists() {
+        std::fs::remove_file(path).map_err(|e| SeedError::io(&context, e))?;
+    }
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o400)
+        .open(path)
+        .and_then(|mut f| f.write_all(&key))
+        .map_err(|e| SeedError::io(&context, e))
+}
+
+/// Creates the LUKS2 container on the partition and enrolls the generated key if 'keyfile' is set
+/// # Arguments
+/// * `device` - The partition to encrypt
+/// * `encrypt` - The encryption config of the partition
+/// * `workdir` - The working directory to keep the generated key in
+/// * `passphrase` - The passphrase of the container
+pub fn encrypt_partition(
+    device: &str,
+    encrypt: &EncryptConf,
+    workdir: &str,
+    passphrase: &str,
+) -> Result<(), SeedError> {
+    info!("Encrypting {} as {}...", device, encrypt.name);
+    run_cryptsetup(&get_luks_format_command(device, encrypt), passphrase)?;
+
+    if encrypt.keyfile.is_some() {
+        let keyfile = get_keyfile_host_path(workdir, encrypt);
+        generate_keyfile(&keyfile)?;
+
+        debug!("Adding the key {} to {}", keyfile.to_string_lossy(), device);
+        run_cryptsetup(
+            &[
+                "cryptsetup".to_owned(),
+                "luksAddKey".to_owned(),
+                "--batch-mode".to_owned(),
+                "--key-file=-".to_owned(),
+                device.to_owned(),
+                keyfile.to_string_lossy().into_owned(),
+            ],
+            passphrase,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Opens the mapping of an encrypted partition at /dev/mapper/<name>
+/// # Arguments
+/// * `device` - The encrypted partition
+/// * `encrypt` - The encryption config of the partition
+/// * `passphrase` - The passphrase of the container
+pub fn open_mapping(
+    device: &str,
+    encrypt: &EncryptConf,
+    passphrase: &str,
+) -> Result<(), SeedError> {
+    info!("Opening {} as /dev/mapper/{}...", device, encrypt.name);
+    run_cryptsetup(
+        &[
+            "cryptsetup".to_owned(),
+            "open".to_owned(),
+            "--key-file=-".to_owned(),
+            device.to_owned(),
+            encrypt.name.clone(),
+        ],
+        passphrase,
+    )
+}
+
+/// Returns if the mapping exists, e.g. because an interrupted installation left it open
+/// # Arguments
+/// * `encrypt` - The encryption config of the partition
+pub fn is_mapping_open(encrypt: &EncryptConf) -> bool {
+    Path::new("/dev/mapper").join(&encrypt.name).exists()
+}
+
+impl SeedConf {
+    /// Closes the mappings of the encrypted partitions seed opened, the partitions need to be unmounted
+    pub fn close_mappings(&mut self) -> Result<(), SeedError> {
+        for cur_disk in &mut self.disks {
+            for cur_part in &mut cur_disk.partitions {
+                let name = match &cur_part.encrypt {
+                    Some(e) if cur_part.mapping_open => e.name.clone(),
+                    _ => continue,
+                };
+
+                debug!("Closing /dev/mapper/{}", name);
+                let what = format!("cryptsetup close {}", name);
+                let output = Command::new("cryptsetup")
+                    .arg("close")
+                    .arg(&name)
+                    .output()
+                    .map_err(|e| SeedError::command(&what, e))?;
+                if !output.status.success() {
+                    return Err(SeedError::command(
+                        &what,
+                        String::from_utf8_lossy(&output.stderr).trim(),
+                    ));
+                }
+                cur_part.mapping_open = false;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,29 +1,100 @@
+use super::crypt::*;
 use super::filesystem::*;
-pub use super::part::configure_partitions;
+pub use super::part::{configure_partitions, set_gpt_guids, PartitionContext};
+use super::resize::*;
+use super::udev::*;
+use super::util::{get_alignment_sectors, get_blkid_tag, get_fs_path, get_part_path, DiskSpace};
 use crate::conf::seed::*;
+use crate::error::SeedError;
+use crate::event::Event;
+use crate::plan::*;
 use libparted::*;
-use std::io;
 
 /// Configures the disks from the seedconf
+/// If `conf.dry_run` is set, the changes only get planned using the in-memory partition tables
 /// # Arguments
 /// * `conf` - The config to implement
-pub fn configure_disks(conf: &mut SeedConf) -> Result<(), io::Error> {
+pub fn configure_disks(conf: &mut SeedConf) -> Result<(), SeedError> {
+    partition_disks(conf)?;
+    create_filesystems(conf)?;
+    mount_disks(conf)
+}
+
+/// Writes the partition tables of the disks
+/// # Arguments
+/// * `conf` - The config to implement
+pub fn partition_disks(conf: &mut SeedConf) -> Result<(), SeedError> {
     //Iterate over every disk
-    //for cur_disk_conf in &conf.disks {
     for cur_disk_index in 0..conf.disks.len() {
-        let cur_disk_conf = &mut conf.disks[cur_disk_index];
-        let mut p_dev = Device::new(&cur_disk_conf.path)?;
-        let p_dev_sector_size = p_dev.sector_size();
-        let mut p_disk = create_disk(&mut p_dev, &cur_disk_conf)?;
+        let cur_disk_conf = &conf.disks[cur_disk_index];
+        if !conf.dry_run {
+            conf.journal.snapshot_disk(&cur_disk_conf.path)?;
+        }
+        let mut p_dev = Device::new(&cur_disk_conf.path)
+            .map_err(|e| SeedError::parted(&cur_disk_conf.path, None, e))?;
+        let mut space = DiskSpace {
+            sector_size: p_dev.sector_size(),
+            total_sectors: p_dev.length() as i64,
+            free_sectors: p_dev.length() as i64,
+            alignment: get_alignment_sectors(&p_dev, cur_disk_conf),
+        };
+        let mut p_disk = create_disk(&mut p_dev, &cur_disk_conf, &mut conf.plan)?;
 
-        //Iterate over the partitions to create them
-        for cur_part_conf in &cur_disk_conf.partitions {
-            configure_partitions(&mut p_disk, cur_part_conf, p_dev_sector_size)?;
+        //Iterate over the partitions to create them, every partition claims space for the next ones
+        for (i, cur_part_conf) in cur_disk_conf.partitions.iter().enumerate() {
+            let mut ctx = PartitionContext {
+                disk: &cur_disk_conf.path,
+                space: &space,
+                workdir: &conf.workdir,
+                plan: &mut conf.plan,
+                journal: &mut conf.journal,
+                dry_run: conf.dry_run,
+            };
+            let size = configure_partitions(&mut p_disk, cur_part_conf, &mut ctx)?;
+            //The logical partitions claim the space of the extended partition
+            if cur_part_conf.get_kind() != PartKind::Extended {
+                space.free_sectors -= size;
+            }
+
+            conf.events.emit(Event::Partition {
+                disk: cur_disk_conf.path.clone(),
+                index: cur_part_conf.index,
+                action: cur_part_conf.action.to_string(),
+                percent: (i + 1) as f32 * 100.0 / cur_disk_conf.partitions.len() as f32,
+            });
+        }
+
+        conf.plan.push(PlanStep::CommitTable {
+            disk: cur_disk_conf.path.clone(),
+        });
+        if !conf.dry_run {
+            info!("Commiting to disk...");
+            //Commit that to disk
+            p_disk
+                .commit()
+                .map_err(|e| SeedError::parted(&cur_disk_conf.path, None, e))?;
+            conf.journal.finish_step(format!(
+                "Committed partition table of {}",
+                cur_disk_conf.path
+            ))?;
         }
 
-        info!("Commiting to disk...");
-        //Commit that to disk
-        p_disk.commit()?;
+        //The paths and sizes the kernel has to report for the partitions once the nodes exist
+        //The kernel only exposes the boot record of extended partitions
+        let expected: Vec<(usize, String, u64)> = cur_disk_conf
+            .partitions
+            .iter()
+            .filter(|p| p.get_kind() != PartKind::Extended)
+            .filter_map(|p| {
+                p_disk.get_partition(p.index as u32).map(|p_part| {
+                    (
+                        p.index,
+                        p_part.get_path().unwrap().to_str().unwrap().to_owned(),
+                        p_part.geom_length() as u64 * space.sector_size,
+                    )
+                })
+            })
+            .collect();
 
         //Drop the disk and the device to force a sync operation
         drop(p_disk);
@@ -31,12 +102,125 @@ pub fn configure_disks(conf: &mut SeedConf) -> Result<(), io::Error> {
 
         crate::libc::sync();
 
-        //Create filesystems on the new partitions
+        //libparted can't set every GUID, sgdisk sets them on the committed table
+        if !conf.dry_run {
+            for cur_part_conf in &cur_disk_conf.partitions {
+                if cur_part_conf.action != PartAction::Keep {
+                    set_gpt_guids(&cur_disk_conf.path, cur_part_conf)?;
+                }
+            }
+        }
+
+        //mkfs can only run once udev created the nodes of the new partitions
+        if !conf.dry_run && is_block_device(&cur_disk_conf.path) {
+            reread_partitions(&cur_disk_conf.path);
+            for (index, part_path, bytes) in expected {
+                wait_for_partition(&cur_disk_conf.path, index, &part_path, bytes)?;
+            }
+            info!("Partitions of {} are ready", cur_disk_conf.path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Encrypts the partitions that get a new filesystem and opens the mappings of all encrypted partitions
+/// # Arguments
+/// * `conf` - The config to implement
+/// * `format` - If new containers get created, false when resuming after they got created
+pub fn open_mappings(conf: &mut SeedConf, format: bool) -> Result<(), SeedError> {
+    for cur_disk_id in 0..conf.disks.len() {
+        for cur_part_id in 0..conf.disks[cur_disk_id].partitions.len() {
+            let cur_disk_conf = &conf.disks[cur_disk_id];
+            let cur_part_conf = &cur_disk_conf.partitions[cur_part_id];
+            let encrypt = match &cur_part_conf.encrypt {
+                Some(e) => e,
+                None => continue,
+            };
+            let part_path = get_part_path(&cur_disk_conf.path, cur_part_conf.index);
+            let create = format
+                && matches!(
+                    cur_part_conf.action,
+                    PartAction::Create | PartAction::Format
+                );
+
+            if create {
+                conf.plan.push(PlanStep::EncryptPartition {
+                    device: part_path.clone(),
+                    mapping: encrypt.name.clone(),
+                    command: get_luks_format_command(&part_path, encrypt),
+                });
+            }
+            conf.plan.push(PlanStep::OpenMapping {
+                device: part_path.clone(),
+                mapping: encrypt.name.clone(),
+            });
+            if conf.dry_run {
+                continue;
+            }
+
+            if !create && is_mapping_open(encrypt) {
+                warn!("/dev/mapper/{} is already open, using it", encrypt.name);
+            } else {
+                let passphrase = read_passphrase(&part_path, encrypt, create)?;
+                if create {
+                    encrypt_partition(&part_path, encrypt, &conf.workdir, &passphrase)?;
+                    conf.journal
+                        .finish_step(format!("Encrypted {}", part_path))?;
+                }
+                open_mapping(&part_path, encrypt, &passphrase)?;
+            }
+            conf.disks[cur_disk_id].partitions[cur_part_id].mapping_open = true;
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates the filesystems on the partitioned disks and grows resized ones
+/// Encrypted partitions get their container first, the filesystem goes into the mapping
+/// # Arguments
+/// * `conf` - The config to implement
+pub fn create_filesystems(conf: &mut SeedConf) -> Result<(), SeedError> {
+    open_mappings(conf, true)?;
+
+    for cur_disk_conf in &conf.disks {
         for cur_part_conf in &cur_disk_conf.partitions {
+            let part_path = get_fs_path(&cur_disk_conf.path, cur_part_conf);
+
             //Only if action is CREATE or FORMAT
             match cur_part_conf.action {
-                PartAction::Create | PartAction::Format => {
-                    create_filesystem(&cur_disk_conf, cur_part_conf)?
+                PartAction::Create | PartAction::Format if cur_part_conf.fs.is_some() => {
+                    conf.plan.push(PlanStep::Mkfs {
+                        device: part_path.clone(),
+                        command: get_mkfs_command(&cur_disk_conf, cur_part_conf),
+                    });
+                    if !conf.dry_run {
+                        create_filesystem(&cur_disk_conf, cur_part_conf, &conf.events)?;
+                        conf.journal
+                            .finish_step(format!("Created filesystem on {}", part_path))?;
+                    }
+                }
+                //Let resized filesystems fill their (possibly grown) partition,
+                //encrypted partitions can't be resized, the filesystem is on the partition itself
+                //A dry run opens no mappings, detecting on the partition works there too
+                PartAction::Resize => {
+                    let part_path = get_part_path(&cur_disk_conf.path, cur_part_conf.index);
+                    let fs = get_filesystem_type(&part_path)?;
+                    //fatresize already resized the filesystem together with the partition
+                    if resizes_partition(&fs) {
+                        continue;
+                    }
+                    conf.plan.push(PlanStep::GrowFilesystem {
+                        device: part_path.clone(),
+                        fs: fs.clone(),
+                    });
+                    if !conf.dry_run {
+                        conf.journal.start_resize(&cur_disk_conf.path, &part_path)?;
+                        grow_filesystem(&part_path, &fs, &conf.workdir)?;
+                        conf.journal
+                            .finish_step(format!("Grew filesystem on {}", part_path))?;
+                    }
                 }
                 _ => (),
             }
@@ -45,8 +229,79 @@ pub fn configure_disks(conf: &mut SeedConf) -> Result<(), io::Error> {
         crate::libc::sync();
     }
 
-    conf.mount_partitions()?;
-    crate::libc::sync();
+    Ok(())
+}
+
+/// Mounts the partitions to the workdir
+/// # Arguments
+/// * `conf` - The config to implement
+pub fn mount_disks(conf: &mut SeedConf) -> Result<(), SeedError> {
+    for (cur_disk_id, cur_part_id) in conf.get_mount_order() {
+        let cur_disk = &conf.disks[cur_disk_id];
+        let cur_part = &cur_disk.partitions[cur_part_id];
+        let target = format!(
+            "{}/mount/{}",
+            conf.workdir,
+            cur_part.mount.as_ref().unwrap()
+        );
+        conf.plan.push(PlanStep::Mount {
+            source: get_fs_path(&cur_disk.path, cur_part),
+            target: target.clone(),
+        });
+        if !conf.dry_run && !conf.journal.mounts.contains(&target) {
+            conf.journal.add_mount(target)?;
+        }
+    }
+
+    if !conf.dry_run {
+        conf.mount_partitions()?;
+        crate::libc::sync();
+        conf.journal.finish_step("Mounted partitions".to_owned())?;
+    }
+
+    Ok(())
+}
+
+/// Checks that the partitions of the seedconf exist on the disks
+/// # Arguments
+/// * `conf` - The config to check
+pub fn check_partitions(conf: &SeedConf) -> Result<(), SeedError> {
+    for cur_disk_conf in &conf.disks {
+        for cur_part_conf in &cur_disk_conf.partitions {
+            let part_path = get_part_path(&cur_disk_conf.path, cur_part_conf.index);
+            if !std::path::Path::new(&part_path).exists() {
+                return Err(SeedError::parted(
+                    &cur_disk_conf.path,
+                    Some(cur_part_conf.index),
+                    format!("{} does not exist", part_path),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the partitions of the seedconf have the filesystems seed creates
+/// # Arguments
+/// * `conf` - The config to check
+pub fn check_filesystems(conf: &SeedConf) -> Result<(), SeedError> {
+    for cur_disk_conf in &conf.disks {
+        for cur_part_conf in &cur_disk_conf.partitions {
+            match cur_part_conf.action {
+                PartAction::Create | PartAction::Format if cur_part_conf.fs.is_some() => {
+                    let part_path = get_fs_path(&cur_disk_conf.path, cur_part_conf);
+                    if get_blkid_tag(&part_path, "TYPE")?.is_none() {
+                        return Err(SeedError::Mkfs {
+                            device: part_path,
+                            stderr: "No filesystem found".to_owned(),
+                        });
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
 
     Ok(())
 }
@@ -55,7 +310,12 @@ pub fn configure_disks(conf: &mut SeedConf) -> Result<(), io::Error> {
 /// # Arguments
 /// * `p_dev` - The device to create the partition object from
 /// * `d_conf` - The disk configuration to implement
-fn create_disk<'a>(p_dev: &'a mut Device, d_conf: &DiskConf) -> Result<Disk<'a>, io::Error> {
+/// * `plan` - The plan to record the change in
+fn create_disk<'a>(
+    p_dev: &'a mut Device,
+    d_conf: &DiskConf,
+    plan: &mut Plan,
+) -> Result<Disk<'a>, SeedError> {
     match d_conf.action {
         DiskAction::New => {
             info!(
@@ -63,28 +323,30 @@ fn create_disk<'a>(p_dev: &'a mut Device, d_conf: &DiskConf) -> Result<Disk<'a>,
                 d_conf.table.as_ref().unwrap(),
                 d_conf.path
             );
-            Ok(Disk::new_fresh(
-                p_dev,
-                match DiskType::get(&d_conf.table.as_ref().unwrap()) {
-                    Some(t) => t,
-                    None => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::Unsupported,
-                            format!(
-                                "Unknown partition table {} for {}",
-                                d_conf.table.as_ref().unwrap(),
-                                d_conf.path
-                            ),
-                        ));
-                    }
-                },
-            )?)
+            plan.push(PlanStep::CreateTable {
+                disk: d_conf.path.clone(),
+                table: d_conf.table.clone().unwrap(),
+            });
+            let table = match DiskType::get(&d_conf.table.as_ref().unwrap()) {
+                Some(t) => t,
+                None => {
+                    return Err(SeedError::parted(
+                        &d_conf.path,
+                        None,
+                        format!("Unknown partition table {}", d_conf.table.as_ref().unwrap()),
+                    ));
+                }
+            };
+            Disk::new_fresh(p_dev, table).map_err(|e| SeedError::parted(&d_conf.path, None, e))
         }
 
         //Reuse the existing partition table
         _ => {
             info!("Loading partition table from disk {}...", d_conf.path);
-            Ok(Disk::new(p_dev)?)
+            plan.push(PlanStep::LoadTable {
+                disk: d_conf.path.clone(),
+            });
+            Disk::new(p_dev).map_err(|e| SeedError::parted(&d_conf.path, None, e))
         }
     }
 }
@@ -1,36 +1,153 @@
+use super::util::get_fs_path;
 use crate::conf::disk::*;
-use std::io::{Error, ErrorKind};
-use std::process::Command;
+use crate::error::SeedError;
+use crate::event::*;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
 
-pub fn create_filesystem(d_conf: &DiskConf, p_conf: &PartConf) -> Result<(), std::io::Error> {
+/// Returns the mkfs command line for the partition
+/// # Arguments
+/// * `d_conf` - The disk the partition resides on
+/// * `p_conf` - The partition to create the filesystem on
+pub fn get_mkfs_command(d_conf: &DiskConf, p_conf: &PartConf) -> Vec<String> {
     let fs = match &p_conf.fs {
         Some(f) => f,
         None => panic!("Help! No filesystem set!"),
     };
 
-    let a_fs = format!("-t{}", fs);
-    let a_path = format!("{}{}", d_conf.path, p_conf.index);
-
-    info!("Creating filesystem using 'mkfs {a_fs} {a_path}'");
+    //Swap space has no filesystem, mkfs does not know it
+    let mut command = match p_conf.is_swap() {
+        true => vec!["mkswap".to_owned()],
+        false => vec!["mkfs".to_owned(), format!("-t{}", fs)],
+    };
 
-    let mut command = Command::new("mkfs");
-    command.arg(&a_fs);
+    match &p_conf.label {
+        Some(label) => command.extend(["-L".to_owned(), label.clone()]),
+        None => (),
+    };
+    match &p_conf.fsuuid {
+        Some(uuid) => command.extend(["-U".to_owned(), uuid.clone()]),
+        None => (),
+    };
 
     match &p_conf.fsargs {
-        Some(args) => {
-            command.arg(args);
-        }
+        Some(args) => command.push(args.clone()),
         None => (),
     };
 
-    command.arg(&a_path);
+    command.push(get_fs_path(&d_conf.path, p_conf));
+
+    command
+}
+
+/// Creates the filesystem on the partition, emitting the output of mkfs as events
+/// # Arguments
+/// * `d_conf` - The disk the partition resides on
+/// * `p_conf` - The partition to create the filesystem on
+/// * `events` - The sink to emit the output to
+pub fn create_filesystem(
+    d_conf: &DiskConf,
+    p_conf: &PartConf,
+    events: &EventSink,
+) -> Result<(), SeedError> {
+    let args = get_mkfs_command(d_conf, p_conf);
+    let device = get_fs_path(&d_conf.path, p_conf);
+
+    info!("Creating filesystem using '{}'", args.join(" "));
+
+    let mkfs_error = |e: std::io::Error| SeedError::Mkfs {
+        device: device.clone(),
+        stderr: format!("Failed to run {}: {}", args[0], e),
+    };
+
+    let mut child = Command::new(&args[0])
+        .args(&args[1..])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(mkfs_error)?;
 
-    let output = command.output()?;
+    //Forward the output while mkfs runs, both pipes need to be drained or mkfs blocks on the full one
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let (stdout, stderr) = std::thread::scope(|s| {
+        let stderr = s.spawn(|| forward_output(stderr, &device, events));
+        let stdout = forward_output(stdout, &device, events);
+        (stdout, stderr.join().expect("mkfs stderr thread panicked"))
+    });
+    stdout.map_err(mkfs_error)?;
+    let stderr = stderr.map_err(mkfs_error)?;
 
-    if !output.status.success() {
-        let err_msg = String::from_utf8(output.stderr).unwrap().replace("\n", "");
-        return Err(Error::new(ErrorKind::Other, err_msg));
+    let status = child.wait().map_err(mkfs_error)?;
+
+    if !status.success() {
+        return Err(SeedError::Mkfs {
+            device,
+            stderr: stderr.join(""),
+        });
     }
 
     Ok(())
 }
+
+/// Emits every line of the output of mkfs as an event
+/// # Arguments
+/// * `output` - The stream to read the lines from
+/// * `device` - The device the filesystem gets created on
+/// * `events` - The sink to emit the output to
+/// # Returns
+/// The lines that were read
+fn forward_output<R: Read>(
+    output: R,
+    device: &str,
+    events: &EventSink,
+) -> std::io::Result<Vec<String>> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in BufReader::new(output).lines() {
+        let line = line?;
+        debug!("[mkfs] {}", line);
+        events.emit(Event::MkfsOutput {
+            device: device.to_owned(),
+            line: line.clone(),
+        });
+        lines.push(line);
+    }
+
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Collects the emitted events
+    #[derive(Clone, Default)]
+    struct Collector(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Collector {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn output_lines_become_events() {
+        let collector = Collector::default();
+        let events = EventSink::from_writer(Box::new(collector.clone()));
+
+        let lines = forward_output(&b"first\nsecond\n"[..], "/dev/sdx1", &events).unwrap();
+        assert_eq!(lines, vec!["first", "second"]);
+
+        let emitted = String::from_utf8(collector.0.lock().unwrap().clone()).unwrap();
+        let emitted: Vec<&str> = emitted.lines().collect();
+        assert_eq!(emitted.len(), 2);
+        assert!(emitted[1].contains("\"line\":\"second\""));
+        assert!(emitted[1].contains("\"device\":\"/dev/sdx1\""));
+    }
+}
@@ -1,5 +1,10 @@
+pub mod crypt;
 pub mod diskmanager;
 pub mod filesystem;
 pub mod mount;
 pub mod part;
+pub mod probe;
+pub mod resize;
+pub mod safety;
+pub mod udev;
 pub mod util;
@@ -1,47 +1,56 @@
 extern crate sys_mount;
 
-use std::io::{Error, ErrorKind};
+use crate::error::SeedError;
 use std::path::*;
 use sys_mount::*;
 
+use super::util::get_fs_path;
+
 pub use crate::conf::disk::DiskConf;
 pub use crate::conf::part::PartConf;
 pub use crate::conf::seed::SeedConf;
 
 impl PartConf {
-    pub fn mount(&mut self, workdir: &str, disk_path: &str) -> std::io::Result<()> {
+    pub fn mount(&mut self, workdir: &str, disk_path: &str) -> Result<(), SeedError> {
         let mount = match &self.mount {
-            Some(m) => Ok(m),
-            None => Err(Error::new(
-                ErrorKind::Other,
-                "PartConf: Missing attribute 'mount'",
-            )),
-        }?;
-
-        let mount_source = format!("{}{}", disk_path, self.index);
+            Some(m) => m,
+            None => {
+                return Err(SeedError::parted(
+                    disk_path,
+                    Some(self.index),
+                    "Missing attribute 'mount'",
+                ))
+            }
+        };
+
+        let mount_source = get_fs_path(disk_path, self);
         let mount_point = format!("{}/mount/{}", workdir, mount);
 
         if self.mount_point.is_some() {
-            return Err(Error::new(
-                ErrorKind::AlreadyExists,
-                format!("Mount point {} is already mounted", mount_point),
-            ));
+            return Err(SeedError::mount(&mount_point, "Already mounted"));
         }
 
         debug!("Mounting '{}' to '{}'...", mount_source, mount_point);
-        std::fs::create_dir_all(&mount_point)?;
-
-        self.mount_point = Some(Mount::builder().mount(mount_source, mount_point)?);
+        std::fs::create_dir_all(&mount_point).map_err(|e| SeedError::mount(&mount_point, e))?;
+
+        self.mount_point = Some(
+            Mount::builder()
+                .mount(&mount_source, &mount_point)
+                .map_err(|e| {
+                    SeedError::mount(&mount_point, format!("Mounting {}: {}", mount_source, e))
+                })?,
+        );
         crate::libc::sync();
 
         Ok(())
     }
 
-    pub fn unmount(&mut self) -> std::io::Result<()> {
+    pub fn unmount(&mut self) -> Result<(), SeedError> {
         match &self.mount_point {
             Some(s) => {
                 debug!("Unmounting {}", self.mount.as_ref().unwrap());
-                s.unmount(UnmountFlags::empty())?;
+                s.unmount(UnmountFlags::empty())
+                    .map_err(|e| SeedError::mount(self.mount.as_ref().unwrap(), e))?;
                 self.mount_point = None;
                 Ok(())
             }
@@ -55,50 +64,51 @@ impl PartConf {
 }
 
 impl SeedConf {
-    /// Mounts the partitions of the seed config in the correct order
-    pub fn mount_partitions(&mut self) -> std::io::Result<()> {
-        let mut cur_d: usize = 1;
-        let mut max_d: usize = 2;
-
-        loop {
-            for cur_disk_id in 0..self.disks.len() {
-                let cur_disk = &mut self.disks[cur_disk_id];
-
-                for cur_part_id in 0..cur_disk.partitions.len() {
-                    let cur_part = &mut cur_disk.partitions[cur_part_id];
-
-                    let mount_depth = Path::new(
-                        cur_part
-                            .mount
-                            .as_ref()
-                            .expect("'mount' missing from PartConf, was validate() called?")
-                            .as_str(),
-                    )
-                    .iter()
-                    .count();
-
-                    if mount_depth > max_d {
-                        max_d = mount_depth + 1;
-                    }
-
-                    if mount_depth == cur_d {
-                        cur_part.mount(&self.workdir, &cur_disk.path)?;
-                    }
+    /// Returns the partitions to mount, parents before their children
+    /// # Returns
+    /// The indices of the disk and the partition in the disk
+    pub fn get_mount_order(&self) -> Vec<(usize, usize)> {
+        let mut res: Vec<(usize, usize, usize)> = Vec::new();
+
+        for (cur_disk_id, cur_disk) in self.disks.iter().enumerate() {
+            for (cur_part_id, cur_part) in cur_disk.partitions.iter().enumerate() {
+                //Partitions without a mount point and swap space are left alone
+                if cur_part.is_swap() {
+                    continue;
+                }
+                match &cur_part.mount {
+                    Some(m) => res.push((
+                        Path::new(m.as_str()).iter().count(),
+                        cur_disk_id,
+                        cur_part_id,
+                    )),
+                    None => continue,
                 }
             }
+        }
 
-            cur_d += 1;
+        //Sort by mount depth, keeping the order of the installfile
+        res.sort_by_key(|(depth, _, _)| *depth);
+        res.into_iter().map(|(_, d, p)| (d, p)).collect()
+    }
 
-            if max_d == cur_d {
-                break;
-            }
+    /// Mounts the partitions of the seed config in the correct order
+    pub fn mount_partitions(&mut self) -> Result<(), SeedError> {
+        for (cur_disk_id, cur_part_id) in self.get_mount_order() {
+            let cur_disk = &mut self.disks[cur_disk_id];
+            cur_disk.partitions[cur_part_id].mount(&self.workdir, &cur_disk.path)?;
         }
 
         Ok(())
     }
 
     /// Unmounts the partitions of the seed config in the correct order
-    pub fn unmount_partitions(&mut self) -> std::io::Result<()> {
+    pub fn unmount_partitions(&mut self) -> Result<(), SeedError> {
+        //Nothing to unmount
+        if self.get_max_mount_depth() == 0 {
+            return Ok(());
+        }
+
         let mut cur_d: usize = self.get_max_mount_depth();
         let mut min_d: usize = self.get_max_mount_depth() - 1;
 
@@ -109,15 +119,11 @@ impl SeedConf {
                 for cur_part_id in 0..cur_disk.partitions.len() {
                     let cur_part = &mut cur_disk.partitions[cur_part_id];
 
-                    let mount_depth = Path::new(
-                        cur_part
-                            .mount
-                            .as_ref()
-                            .expect("'mount' missing from PartConf, was validate() called?")
-                            .as_str(),
-                    )
-                    .iter()
-                    .count();
+                    //Partitions without a mount point are left alone
+                    let mount_depth = match &cur_part.mount {
+                        Some(m) => Path::new(m.as_str()).iter().count(),
+                        None => continue,
+                    };
 
                     if mount_depth < min_d {
                         min_d = mount_depth - 1;
@@ -144,9 +150,10 @@ impl SeedConf {
         let mut max_d: usize = 0;
         for cur_disk in &self.disks {
             for cur_part in &cur_disk.partitions {
-                let mount_depth = Path::new(cur_part.mount.as_ref().unwrap().as_str())
-                    .iter()
-                    .count();
+                let mount_depth = match &cur_part.mount {
+                    Some(m) => Path::new(m.as_str()).iter().count(),
+                    None => continue,
+                };
                 if mount_depth > max_d {
                     max_d = mount_depth;
                 }
@@ -1,95 +1,452 @@
+use super::resize::*;
 use super::util::*;
 use crate::conf::part::*;
+use crate::error::SeedError;
+use crate::journal::Journal;
+use crate::plan::*;
 use libparted::*;
-use std::io;
+use std::process::Command;
+
+/// Returns the libparted flag that sets the partition type, if libparted has one for it
+/// # Arguments
+/// * `part_type` - The partition type
+fn get_type_flag(part_type: &PartType) -> Option<PartitionFlag> {
+    match part_type {
+        PartType::Esp => Some(PartitionFlag::PED_PARTITION_ESP),
+        PartType::BiosGrub => Some(PartitionFlag::PED_PARTITION_BIOS_GRUB),
+        PartType::Swap => Some(PartitionFlag::PED_PARTITION_SWAP),
+        _ => None,
+    }
+}
+
+/// Returns the libparted flag of a partition attribute flag
+/// # Arguments
+/// * `flag` - The partition attribute flag
+fn get_part_flag(flag: &PartFlag) -> PartitionFlag {
+    match flag {
+        PartFlag::Boot => PartitionFlag::PED_PARTITION_BOOT,
+        PartFlag::LegacyBoot => PartitionFlag::PED_PARTITION_LEGACY_BOOT,
+        PartFlag::Hidden => PartitionFlag::PED_PARTITION_HIDDEN,
+    }
+}
+
+/// Sets a flag of the partition, if the partition table supports it
+/// # Arguments
+/// * `p_part` - The partition
+/// * `flag` - The flag to set
+/// * `what` - The name of the flag (for context)
+fn set_flag(p_part: &mut Partition, flag: PartitionFlag, what: &str) -> Result<(), String> {
+    if !p_part.is_flag_available(flag) {
+        return Err(format!(
+            "'{}' is not available on this partition table",
+            what
+        ));
+    }
+    p_part
+        .set_flag(flag, true)
+        .map_err(|e| format!("Failed to set '{}': {}", what, e))
+}
+
+/// Sets the type, name and flags of a partition in the in-memory partition table
+/// Types libparted has no flag for and the UUID get set by set_gpt_guids() after committing the table
+/// # Arguments
+/// * `p_part` - The partition
+/// * `is_gpt` - If the partition table is a GPT
+/// * `p_conf` - The partition config
+fn set_partition_attributes(
+    p_part: &mut Partition,
+    is_gpt: bool,
+    p_conf: &PartConf,
+) -> Result<(), String> {
+    match &p_conf.part_type {
+        Some(t) => match get_type_flag(t) {
+            Some(flag) => set_flag(p_part, flag, &t.to_string())?,
+            //New msdos partitions already are linux partitions
+            None if !is_gpt && *t != PartType::Linux => {
+                return Err(format!("Partition type '{}' requires a GPT", t))
+            }
+            None => (),
+        },
+        None => (),
+    }
+
+    match &p_conf.name {
+        Some(name) if !is_gpt => return Err(format!("Name '{}' requires a GPT", name)),
+        Some(name) => p_part
+            .set_name(name)
+            .map_err(|e| format!("Failed to set name '{}': {}", name, e))?,
+        None => (),
+    }
+
+    for flag in p_conf.flags.iter().flatten() {
+        set_flag(p_part, get_part_flag(flag), &flag.to_string())?;
+    }
+
+    Ok(())
+}
+
+/// Returns the partition attributes for the plan
+/// # Arguments
+/// * `p_conf` - The partition config
+fn describe_attributes(p_conf: &PartConf) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+    match &p_conf.part_type {
+        Some(t) => res.push(format!("type {}", t)),
+        None => (),
+    }
+    match &p_conf.name {
+        Some(n) => res.push(format!("name '{}'", n)),
+        None => (),
+    }
+    match &p_conf.uuid {
+        Some(u) => res.push(format!("uuid {}", u)),
+        None => (),
+    }
+    for flag in p_conf.flags.iter().flatten() {
+        res.push(format!("flag {}", flag));
+    }
+    res
+}
+
+/// Sets the type GUIDs libparted has no flag for and the partition UUID of a GPT partition,
+/// libparted can't set these, so sgdisk sets them on the committed table
+/// # Arguments
+/// * `d_path` - The path to the disk
+/// * `p_conf` - The partition config
+pub fn set_gpt_guids(d_path: &str, p_conf: &PartConf) -> Result<(), SeedError> {
+    let mut args: Vec<String> = Vec::new();
+    match &p_conf.part_type {
+        Some(t) if get_type_flag(t).is_none() => {
+            args.push(format!("--typecode={}:{}", p_conf.index, t.guid()))
+        }
+        _ => (),
+    }
+    match &p_conf.uuid {
+        Some(u) => args.push(format!("--partition-guid={}:{}", p_conf.index, u)),
+        None => (),
+    }
+    if args.is_empty() {
+        return Ok(());
+    }
+
+    let what = format!("sgdisk {} {}", args.join(" "), d_path);
+    debug!("Running {}", what);
+    let output = Command::new("sgdisk")
+        .args(&args)
+        .arg(d_path)
+        .output()
+        .map_err(|e| SeedError::command(&what, e))?;
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(SeedError::command(
+            &what,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        )),
+    }
+}
+
+/// What configure_partitions() needs besides the partition itself
+pub struct PartitionContext<'a> {
+    /// The path to the disk
+    pub disk: &'a str,
+    /// The space available on the disk
+    pub space: &'a DiskSpace,
+    /// The seed working directory
+    pub workdir: &'a str,
+    /// The plan to record the changes in
+    pub plan: &'a mut Plan,
+    /// The journal to record the filesystem changes in
+    pub journal: &'a mut Journal,
+    /// Only change the in-memory partition table, not the filesystems
+    pub dry_run: bool,
+}
 
 /// Configures the partitions on the provided disk according to the partition config
 /// # Arguments
 /// * `p_disk` - The disk to manipulate
 /// * `p_conf` - The partition config
-/// * `sector_size` - The sector size on the physical disk
-pub fn configure_partitions<'a>(
+/// * `ctx` - The disk, its space and where to record the changes
+/// # Returns
+/// The amount of sectors the partition occupies
+pub fn configure_partitions(
     p_disk: &mut Disk,
     p_conf: &PartConf,
-    sector_size: u64,
-) -> Result<(), io::Error> {
+    ctx: &mut PartitionContext,
+) -> Result<i64, SeedError> {
+    let d_path = ctx.disk;
+    let parted_error = |msg: String| SeedError::parted(d_path, Some(p_conf.index), msg);
+    let is_gpt = p_disk.get_disk_type_name() == Some(b"gpt".as_slice());
+
+    //The table entries of kept partitions are left as they are
+    if p_conf.action != PartAction::Keep && p_conf.has_attributes() {
+        ctx.plan.push(PlanStep::SetPartitionAttributes {
+            disk: d_path.to_owned(),
+            index: p_conf.index,
+            attributes: describe_attributes(p_conf),
+        });
+    }
+
     match &p_conf.action {
         //Create a partititon
         PartAction::Create => {
             //The partition can't exist already
             match p_disk.get_partition(p_conf.index as u32) {
                 Some(p_part) => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::AlreadyExists,
-                        format!(
-                            "Can't create partition {}: Already exists at {}",
-                            p_conf.index,
-                            p_part.get_path().unwrap().to_str().unwrap()
-                        ),
-                    ))
+                    return Err(parted_error(format!(
+                        "Can't create partition: Already exists at {}",
+                        p_part.get_path().unwrap().to_str().unwrap()
+                    )))
                 }
                 None => (),
             }
 
-            //Find the starting sector for the new disk
-            let start_sector = match get_next_possible_start(p_disk) {
-                Some(v) => v,
-                None => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "Unable to determine start of new partition",
-                    ))
-                }
+            //Extended and logical partitions only exist on msdos tables
+            let kind = p_conf.get_kind();
+            let part_type = match kind {
+                PartKind::Primary => PartitionType::PED_PARTITION_NORMAL,
+                PartKind::Extended => PartitionType::PED_PARTITION_EXTENDED,
+                PartKind::Logical => PartitionType::PED_PARTITION_LOGICAL,
             };
+            if kind != PartKind::Primary && p_disk.get_disk_type_name() != Some(b"msdos".as_slice())
+            {
+                return Err(parted_error(format!(
+                    "Can't create {} partition: Only msdos tables support them",
+                    kind
+                )));
+            }
+            if kind == PartKind::Logical && p_disk.get_extended_partition().is_none() {
+                return Err(parted_error(
+                    "Can't create logical partition: The disk has no extended partition".to_owned(),
+                ));
+            }
+
+            //Find the aligned start of the largest free region
+            let (start_sector, max_end) =
+                match get_next_possible_start(p_disk, ctx.space, kind == PartKind::Logical) {
+                    Some(v) => v,
+                    None => {
+                        return Err(parted_error(
+                            "Unable to determine start of new partition: No free space left"
+                                .to_owned(),
+                        ))
+                    }
+                };
 
-            //Calculate the size
-            let size = get_part_size_sectors(p_conf.size.as_ref().unwrap(), sector_size);
+            //Calculate the size, 'max' fills the free region
+            let size = match p_conf.size.as_ref().unwrap() {
+                PartSize::Total(PartTotal::Max) => max_end - start_sector + 1,
+                size => get_part_size_sectors(p_disk, size, ctx.space, start_sector)
+                    .map_err(parted_error)?,
+            };
+            if start_sector + size - 1 > max_end {
+                return Err(parted_error(format!(
+                    "Not enough free space: {} sectors needed, the largest free region has {}",
+                    size,
+                    max_end - start_sector + 1
+                )));
+            }
 
             //Inform the user about the cange
             info!(
                 "Creating new partition nr.{}: start = sector {}, end = sector {}",
                 p_conf.index,
                 start_sector,
-                start_sector + size
+                start_sector + size - 1
             );
 
             //Create the new partition
             let mut new_part = Partition::new(
                 p_disk,
-                PartitionType::PED_PARTITION_NORMAL,
+                part_type,
                 None,
                 start_sector,
-                start_sector + size,
-            )?;
+                start_sector + size - 1,
+            )
+            .map_err(|e| parted_error(e.to_string()))?;
 
             //And add it to the disk
-            p_disk.add_partition(&mut new_part, p_disk.constraint_any().as_ref().unwrap())?;
+            p_disk
+                .add_partition(&mut new_part, p_disk.constraint_any().as_ref().unwrap())
+                .map_err(|e| parted_error(e.to_string()))?;
 
-            Ok(())
+            //libparted numbers the partition, the rest of seed relies on the configured index
+            if new_part.num() as usize != p_conf.index {
+                return Err(parted_error(format!(
+                    "The new partition got number {} instead",
+                    new_part.num()
+                )));
+            }
+            set_partition_attributes(&mut new_part, is_gpt, p_conf).map_err(parted_error)?;
+
+            ctx.plan.push(PlanStep::CreatePartition {
+                disk: d_path.to_owned(),
+                index: p_conf.index,
+                start: new_part.geom_start(),
+                end: new_part.geom_end(),
+                sector_size: ctx.space.sector_size,
+            });
+
+            Ok(size)
         }
 
         //We don't need to alter the partition
         PartAction::Keep | PartAction::Format => match p_disk.get_partition(p_conf.index as u32) {
-            Some(p_part) => {
+            Some(mut p_part) => {
+                if p_conf.action == PartAction::Format {
+                    set_partition_attributes(&mut p_part, is_gpt, p_conf).map_err(parted_error)?;
+                }
+
+                //An exact size on a kept partition describes the partition that is expected
+                match (&p_conf.action, &p_conf.size) {
+                    (PartAction::Keep, Some(PartSize::Size(count, unit))) => {
+                        let expected = bytes_to_sectors(
+                            data_size_bytes(*count, unit)
+                                .expect("Invalid size, was validate() called?"),
+                            ctx.space.sector_size,
+                        ) as i64;
+                        if expected != p_part.geom_length() {
+                            return Err(parted_error(format!(
+                                "Partition has {} sectors, expected {}",
+                                p_part.geom_length(),
+                                expected
+                            )));
+                        }
+                    }
+                    _ => (),
+                }
+
                 info!(
                     "Found partition {} at path {}",
                     p_conf.index,
                     p_part.get_path().unwrap().to_str().unwrap()
                 );
-                Ok(())
-            }
-            None => {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Could not find partition with index {}", p_conf.index),
-                ))
+                ctx.plan.push(PlanStep::KeepPartition {
+                    disk: d_path.to_owned(),
+                    index: p_conf.index,
+                    start: p_part.geom_start(),
+                    end: p_part.geom_end(),
+                    sector_size: ctx.space.sector_size,
+                });
+                Ok(p_part.geom_length())
             }
+            None => return Err(parted_error("Could not find partition".to_owned())),
         },
 
-        //Error on any unimplememted options
-        other => Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Partition action '{:?}': Not yet implemented", other),
-        )),
+        //Resize the partition, shrinking the filesystem first if needed
+        PartAction::Resize => {
+            let mut p_part = match p_disk.get_partition(p_conf.index as u32) {
+                Some(p) => p,
+                None => return Err(parted_error("Could not find partition".to_owned())),
+            };
+            let part_path = p_part.get_path().unwrap().to_str().unwrap().to_owned();
+
+            let start_sector = p_part.geom_start();
+            let old_size = p_part.geom_length();
+            let fs = get_filesystem_type(&part_path)?;
+
+            //'min' depends on the contents of the filesystem, a dry run does not mount it to find out
+            if matches!(p_conf.size, Some(PartSize::Total(PartTotal::Min)))
+                && ctx.dry_run
+                && min_size_needs_mount(&fs)
+            {
+                check_resizable(&part_path, &fs, true)?;
+                warn!(
+                    "The minimum size of {} is unknown in a dry run, the plan keeps its current size",
+                    part_path
+                );
+                set_partition_attributes(&mut p_part, is_gpt, p_conf).map_err(parted_error)?;
+                ctx.plan.push(PlanStep::ResizePartition {
+                    disk: d_path.to_owned(),
+                    index: p_conf.index,
+                    fs,
+                    start: start_sector,
+                    old_end: p_part.geom_end(),
+                    end: None,
+                    sector_size: ctx.space.sector_size,
+                });
+                return Ok(old_size);
+            }
+
+            let new_size = match p_conf.size.as_ref().unwrap() {
+                PartSize::Total(PartTotal::Min) => bytes_to_sectors(
+                    get_min_filesystem_size(&part_path, &fs, ctx.workdir)?,
+                    ctx.space.sector_size,
+                ) as i64,
+                size => get_part_size_sectors(p_disk, size, ctx.space, start_sector)
+                    .map_err(parted_error)?,
+            };
+
+            if new_size == old_size {
+                info!(
+                    "Partition {} already has the requested size, skipping resize",
+                    part_path
+                );
+                return Ok(old_size);
+            }
+
+            //Make sure we can handle the filesystem before touching anything
+            check_resizable(&part_path, &fs, new_size < old_size)?;
+
+            info!(
+                "Resizing partition nr.{}: start = sector {}, end = sector {} (was {})",
+                p_conf.index,
+                start_sector,
+                start_sector + new_size - 1,
+                p_part.geom_end()
+            );
+
+            //This fails if the space is taken in the in-memory partition table
+            p_disk
+                .set_partition_geom(
+                    &mut p_part,
+                    p_disk.constraint_any().as_ref().unwrap(),
+                    start_sector,
+                    start_sector + new_size - 1,
+                )
+                .map_err(|e| parted_error(e.to_string()))?;
+
+            if !ctx.dry_run && resizes_partition(&fs) {
+                //fatresize writes the partition itself, the committed table has to keep its geometry
+                ctx.journal.start_resize(d_path, &part_path)?;
+                resize_fat_partition(&part_path, new_size as u64 * ctx.space.sector_size)?;
+                ctx.journal
+                    .finish_step(format!("Resized filesystem and partition {}", part_path))?;
+
+                let (start, end) =
+                    read_partition_geom(d_path, p_conf.index, ctx.space.sector_size)?;
+                p_disk
+                    .set_partition_geom(
+                        &mut p_part,
+                        p_disk.constraint_any().as_ref().unwrap(),
+                        start,
+                        end,
+                    )
+                    .map_err(|e| parted_error(e.to_string()))?;
+            } else if !ctx.dry_run && new_size < old_size {
+                //The filesystem needs to shrink before the partition does
+                ctx.journal.start_resize(d_path, &part_path)?;
+                shrink_filesystem(
+                    &part_path,
+                    &fs,
+                    new_size as u64 * ctx.space.sector_size,
+                    ctx.workdir,
+                )?;
+                ctx.journal
+                    .finish_step(format!("Shrunk filesystem on {}", part_path))?;
+            }
+            set_partition_attributes(&mut p_part, is_gpt, p_conf).map_err(parted_error)?;
+
+            ctx.plan.push(PlanStep::ResizePartition {
+                disk: d_path.to_owned(),
+                index: p_conf.index,
+                fs,
+                start: start_sector,
+                old_end: start_sector + old_size - 1,
+                end: Some(p_part.geom_end()),
+                sector_size: ctx.space.sector_size,
+            });
+
+            Ok(p_part.geom_length())
+        }
     }
 }
@@ -0,0 +1,194 @@
+use super::util::*;
+use crate::error::SeedError;
+use crate::plan::format_sectors;
+use libparted::*;
+use serde::Serialize;
+use std::fmt;
+
+/// A partition found on a disk
+#[derive(Serialize, Debug)]
+pub struct ProbedPartition {
+    pub index: usize,
+    pub path: Option<String>,
+    /// 'primary', 'logical' or 'extended'
+    pub kind: String,
+    pub start: i64,
+    pub end: i64,
+    pub fs: Option<String>,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    /// The GPT partition name
+    pub name: Option<String>,
+}
+
+/// A region of unpartitioned space on a disk
+#[derive(Serialize, Debug)]
+pub struct FreeRegion {
+    pub start: i64,
+    pub end: i64,
+}
+
+/// A block device and its partition table as seen by libparted
+#[derive(Serialize, Debug)]
+pub struct ProbedDisk {
+    pub path: String,
+    pub model: String,
+    /// The size in bytes
+    pub size: u64,
+    pub sector_size: u64,
+    pub phys_sector_size: u64,
+    /// The partition table type, None if the disk has no partition table
+    pub table: Option<String>,
+    pub partitions: Vec<ProbedPartition>,
+    pub free: Vec<FreeRegion>,
+}
+
+/// Queries a blkid tag of a partition, failing to query it is not fatal
+/// # Arguments
+/// * `path` - The path to the partition, if libparted knows it
+/// * `tag` - The tag to query
+fn probe_tag(path: &Option<String>, tag: &str) -> Option<String> {
+    let path = path.as_ref()?;
+    match get_blkid_tag(path, tag) {
+        Ok(v) => v,
+        Err(e) => {
+            debug!("{}", e.to_string());
+            None
+        }
+    }
+}
+
+/// Reads the partitions and free regions of a libparted disk
+/// # Arguments
+/// * `p_disk` - The disk to read
+/// * `probed` - The disk to fill in
+fn probe_table(p_disk: &Disk, probed: &mut ProbedDisk) {
+    probed.table = p_disk
+        .get_disk_type_name()
+        .map(|n| String::from_utf8_lossy(n).into_owned());
+
+    for p_part in p_disk.parts() {
+        if is_free_region(&p_part) {
+            probed.free.push(FreeRegion {
+                start: p_part.geom_start(),
+                end: p_part.geom_end(),
+            });
+            continue;
+        }
+
+        //Metadata regions have no partition number
+        if p_part.num() < 1 {
+            continue;
+        }
+
+        let path = p_part.get_path().map(|p| p.to_string_lossy().into_owned());
+        probed.partitions.push(ProbedPartition {
+            index: p_part.num() as usize,
+            kind: p_part.type_get_name().to_owned(),
+            start: p_part.geom_start(),
+            end: p_part.geom_end(),
+            fs: probe_tag(&path, "TYPE"),
+            label: probe_tag(&path, "LABEL"),
+            uuid: probe_tag(&path, "UUID"),
+            name: p_part.name().map(|n| n.to_owned()),
+            path,
+        });
+    }
+}
+
+/// Probes a single block device
+/// # Arguments
+/// * `p_dev` - The device to probe
+fn probe_device(p_dev: &mut Device) -> ProbedDisk {
+    let mut probed = ProbedDisk {
+        path: p_dev.path().to_string_lossy().into_owned(),
+        model: p_dev.model().to_owned(),
+        size: p_dev.length() * p_dev.sector_size(),
+        sector_size: p_dev.sector_size(),
+        phys_sector_size: p_dev.phys_sector_size(),
+        table: None,
+        partitions: Vec::new(),
+        free: Vec::new(),
+    };
+
+    //libparted fails to read disks without a partition table
+    match Disk::new(p_dev) {
+        Ok(p_disk) => probe_table(&p_disk, &mut probed),
+        Err(e) => debug!("No partition table on {}: {}", probed.path, e),
+    }
+
+    probed
+}
+
+/// Probes the block device at the path
+/// # Arguments
+/// * `path` - The path to the block device
+pub fn probe_disk(path: &str) -> Result<ProbedDisk, SeedError> {
+    let mut p_dev = Device::new(path).map_err(|e| SeedError::parted(path, None, e))?;
+    Ok(probe_device(&mut p_dev))
+}
+
+/// Lists every block device of the system with its partitions and free space
+pub fn probe_disks() -> Vec<ProbedDisk> {
+    let mut res: Vec<ProbedDisk> = Device::devices(true)
+        .map(|mut p_dev| probe_device(&mut p_dev))
+        .collect();
+    res.sort_by(|a, b| a.path.cmp(&b.path));
+    res
+}
+
+impl fmt::Display for ProbedDisk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} ({}): {}, {} byte sectors ({} physical), {}",
+            self.path,
+            self.model,
+            format_sectors(0, self.size as i64 - 1, 1),
+            self.sector_size,
+            self.phys_sector_size,
+            match &self.table {
+                Some(t) => format!("{} partition table", t),
+                None => "no partition table".to_owned(),
+            }
+        )?;
+
+        for part in &self.partitions {
+            writeln!(
+                f,
+                "  {:>3}  {}  {} {}  sectors {} - {} ({}){}{}{}",
+                part.index,
+                part.path.as_deref().unwrap_or("?"),
+                part.kind,
+                part.fs.as_deref().unwrap_or("-"),
+                part.start,
+                part.end,
+                format_sectors(part.start, part.end, self.sector_size),
+                match &part.label {
+                    Some(l) => format!(" label '{}'", l),
+                    None => String::new(),
+                },
+                match &part.name {
+                    Some(n) if !n.is_empty() => format!(" name '{}'", n),
+                    _ => String::new(),
+                },
+                match &part.uuid {
+                    Some(u) => format!(" uuid {}", u),
+                    None => String::new(),
+                },
+            )?;
+        }
+
+        for free in &self.free {
+            writeln!(
+                f,
+                "       free  sectors {} - {} ({})",
+                free.start,
+                free.end,
+                format_sectors(free.start, free.end, self.sector_size)
+            )?;
+        }
+
+        Ok(())
+    }
+}
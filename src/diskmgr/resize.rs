@@ -0,0 +1,432 @@
+use super::util::get_blkid_tag;
+use crate::error::SeedError;
+use std::process::Command;
+use sys_mount::*;
+
+/// The filesystems seed knows how to resize
+/// # Fields
+/// * name - The filesystem type as reported by blkid
+/// * shrink - If the filesystem can be shrunk safely
+const RESIZABLE_FILESYSTEMS: &'static [(&'static str, bool)] = &[
+    ("ext2", true),
+    ("ext3", true),
+    ("ext4", true),
+    ("btrfs", true),
+    ("xfs", false),
+    ("vfat", true),
+    ("ntfs", true),
+];
+
+/// Determines the filesystem on the provided partition
+/// # Arguments
+/// * `part_path` - The path to the partition
+pub fn get_filesystem_type(part_path: &str) -> Result<String, SeedError> {
+    match get_blkid_tag(part_path, "TYPE")? {
+        Some(fs) => Ok(fs),
+        None => Err(SeedError::resize(
+            part_path,
+            "Could not detect a filesystem",
+        )),
+    }
+}
+
+/// Checks if the filesystem can be resized in the requested direction
+/// # Arguments
+/// * `part_path` - The path to the partition (for context)
+/// * `fs` - The filesystem type
+/// * `shrink` - If the filesystem should get shrunk
+pub fn check_resizable(part_path: &str, fs: &str, shrink: bool) -> Result<(), SeedError> {
+    match RESIZABLE_FILESYSTEMS.iter().find(|(name, _)| *name == fs) {
+        Some((_, can_shrink)) => {
+            if shrink && !can_shrink {
+                return Err(SeedError::resize(
+                    part_path,
+                    format!("Filesystem '{}' can not be shrunk", fs),
+                ));
+            }
+            Ok(())
+        }
+        None => Err(SeedError::resize(
+            part_path,
+            format!("Resizing filesystem '{}' is not supported", fs),
+        )),
+    }
+}
+
+/// Runs the command and turns a failed exit status into an error
+/// # Arguments
+/// * `part_path` - The path to the resized partition (for context)
+/// * `command` - The command to run
+fn run_resize_command(part_path: &str, command: &mut Command) -> Result<(), SeedError> {
+    get_command_output(part_path, command)?;
+    Ok(())
+}
+
+/// Checks an ext filesystem, resize2fs refuses to operate on unchecked filesystems
+/// # Arguments
+/// * `part_path` - The path to the partition
+fn check_ext_filesystem(part_path: &str) -> Result<(), SeedError> {
+    let output = Command::new("e2fsck")
+        .arg("-f")
+        .arg("-y")
+        .arg(part_path)
+        .output()
+        .map_err(|e| SeedError::resize(part_path, format!("Failed to run e2fsck: {}", e)))?;
+
+    //0: no errors, 1: errors corrected
+    match output.status.code() {
+        Some(0) | Some(1) => Ok(()),
+        _ => Err(SeedError::resize(
+            part_path,
+            format!(
+                "e2fsck failed: {}",
+                String::from_utf8_lossy(&output.stderr).replace("\n", "")
+            ),
+        )),
+    }
+}
+
+/// Temporarily mounts the partition for tools that operate on mounted filesystems
+/// # Arguments
+/// * `part_path` - The path to the partition
+/// * `workdir` - The seed working directory
+/// * `flags` - The flags to mount the partition with
+/// * `func` - The function to run on the mount point
+fn with_temporary_mount<F>(
+    part_path: &str,
+    workdir: &str,
+    flags: MountFlags,
+    func: F,
+) -> Result<(), SeedError>
+where
+    F: FnOnce(&str) -> Result<(), SeedError>,
+{
+    let mount_point = format!("{}/resize", workdir);
+    std::fs::create_dir_all(&mount_point).map_err(|e| SeedError::mount(&mount_point, e))?;
+
+    debug!(
+        "Temporarily mounting '{}' to '{}'...",
+        part_path, mount_point
+    );
+    let mount = Mount::builder()
+        .flags(flags)
+        .mount(part_path, &mount_point)
+        .map_err(|e| SeedError::mount(&mount_point, e))?;
+
+    let res = func(&mount_point);
+
+    debug!("Unmounting {}", mount_point);
+    mount
+        .unmount(UnmountFlags::empty())
+        .map_err(|e| SeedError::mount(&mount_point, e))?;
+    crate::libc::sync();
+
+    res
+}
+
+/// Runs the command and returns its standard output
+/// # Arguments
+/// * `part_path` - The path to the resized partition (for context)
+/// * `command` - The command to run
+fn get_command_output(part_path: &str, command: &mut Command) -> Result<String, SeedError> {
+    debug!("Running {:?}", command);
+    let output = command.output().map_err(|e| {
+        SeedError::resize(
+            part_path,
+            format!("Failed to run {:?}: {}", command.get_program(), e),
+        )
+    })?;
+
+    if !output.status.success() {
+        let err_msg = String::from_utf8_lossy(&output.stderr).replace("\n", "");
+        return Err(SeedError::resize(
+            part_path,
+            format!("{:?} failed: {}", command.get_program(), err_msg),
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Searches the output for a line starting with the prefix and parses the first number after it
+/// # Arguments
+/// * `output` - The output to search
+/// * `prefix` - The prefix of the line
+fn parse_number_after(output: &str, prefix: &str) -> Option<u64> {
+    output
+        .lines()
+        .map(|l| l.trim())
+        .find(|l| l.starts_with(prefix))?
+        .trim_start_matches(prefix)
+        .split(|c: char| !c.is_numeric())
+        .find(|s| !s.is_empty())?
+        .parse()
+        .ok()
+}
+
+/// Returns if determining the minimum size of the filesystem needs a temporary mount
+/// # Arguments
+/// * `fs` - The filesystem type
+pub fn min_size_needs_mount(fs: &str) -> bool {
+    fs == "btrfs"
+}
+
+/// Determines the smallest size the filesystem can be shrunk to without corrupting it
+/// This does not alter the filesystem
+/// # Arguments
+/// * `part_path` - The path to the partition
+/// * `fs` - The filesystem on the partition
+/// * `workdir` - The seed working directory for temporary mounts
+/// # Returns
+/// The minimum size in bytes, rounded up to the next MiB
+pub fn get_min_filesystem_size(part_path: &str, fs: &str, workdir: &str) -> Result<u64, SeedError> {
+    check_resizable(part_path, fs, true)?;
+
+    let parse_error = |tool: &str| {
+        SeedError::resize(part_path, format!("Could not parse the output of {}", tool))
+    };
+
+    let bytes = match fs {
+        "ext2" | "ext3" | "ext4" => {
+            let min = get_command_output(
+                part_path,
+                Command::new("resize2fs").arg("-P").arg(part_path),
+            )?;
+            let blocks = parse_number_after(&min, "Estimated minimum size of the filesystem:")
+                .ok_or_else(|| parse_error("resize2fs"))?;
+            let info =
+                get_command_output(part_path, Command::new("dumpe2fs").arg("-h").arg(part_path))?;
+            let block_size =
+                parse_number_after(&info, "Block size:").ok_or_else(|| parse_error("dumpe2fs"))?;
+            blocks * block_size
+        }
+        "btrfs" => {
+            let mut bytes: Option<u64> = None;
+            with_temporary_mount(part_path, workdir, MountFlags::RDONLY, |mount_point| {
+                let output = get_command_output(
+                    part_path,
+                    Command::new("btrfs")
+                        .arg("inspect-internal")
+                        .arg("min-dev-size")
+                        .arg(mount_point),
+                )?;
+                bytes = parse_number_after(&output, "");
+                Ok(())
+            })?;
+            bytes.ok_or_else(|| parse_error("btrfs"))?
+        }
+        "ntfs" => {
+            let output = get_command_output(
+                part_path,
+                Command::new("ntfsresize")
+                    .arg("-f")
+                    .arg("-i")
+                    .arg(part_path),
+            )?;
+            parse_number_after(&output, "You might resize at")
+                .ok_or_else(|| parse_error("ntfsresize"))?
+        }
+        other => {
+            return Err(SeedError::resize(
+                part_path,
+                format!(
+                    "Can not determine the minimum size of filesystem '{}'",
+                    other
+                ),
+            ))
+        }
+    };
+
+    let mib = 1024 * 1024;
+    Ok(bytes.div_ceil(mib) * mib)
+}
+
+/// Returns if the resize tool of the filesystem also changes the partition in the table on the disk
+/// # Arguments
+/// * `fs` - The filesystem type
+pub fn resizes_partition(fs: &str) -> bool {
+    fs == "vfat"
+}
+
+/// Resizes a FAT filesystem together with its partition, fatresize writes the new partition to the table itself
+/// # Arguments
+/// * `part_path` - The path to the partition
+/// * `bytes` - The new size of the partition in bytes
+pub fn resize_fat_partition(part_path: &str, bytes: u64) -> Result<(), SeedError> {
+    info!(
+        "Resizing vfat filesystem and partition {} to {} bytes",
+        part_path, bytes
+    );
+    run_resize_command(
+        part_path,
+        Command::new("fatresize")
+            .arg("-f")
+            .arg("-s")
+            .arg(bytes.to_string())
+            .arg(part_path),
+    )
+}
+
+/// Reads the geometry of a partition from the table on the disk, e.g. after fatresize changed it
+/// # Arguments
+/// * `disk_path` - The path to the disk
+/// * `index` - The index of the partition
+/// * `sector_size` - The sector size of the disk
+/// # Returns
+/// The first and the last sector of the partition
+pub fn read_partition_geom(
+    disk_path: &str,
+    index: usize,
+    sector_size: u64,
+) -> Result<(i64, i64), SeedError> {
+    let output = get_command_output(
+        disk_path,
+        Command::new("partx")
+            .arg("-g")
+            .arg("-o")
+            .arg("START,END")
+            .arg("-n")
+            .arg(index.to_string())
+            .arg(disk_path),
+    )?;
+
+    parse_partx_geom(&output, sector_size)
+        .ok_or_else(|| SeedError::resize(disk_path, "Could not parse the output of partx"))
+}
+
+/// Parses the 'START END' output of partx into sectors of the disk
+/// # Arguments
+/// * `output` - The output of partx
+/// * `sector_size` - The sector size of the disk
+fn parse_partx_geom(output: &str, sector_size: u64) -> Option<(i64, i64)> {
+    //partx counts in 512 byte sectors, whatever the sector size of the disk is
+    let sectors: Vec<u64> = output
+        .split_whitespace()
+        .filter_map(|s| s.parse().ok())
+        .collect();
+    match sectors[..] {
+        [start, end] => Some((
+            (start * 512 / sector_size) as i64,
+            ((end + 1) * 512 / sector_size) as i64 - 1,
+        )),
+        _ => None,
+    }
+}
+
+/// Shrinks the filesystem on the partition, this has to happen before shrinking the partition
+/// # Arguments
+/// * `part_path` - The path to the partition
+/// * `fs` - The filesystem on the partition
+/// * `bytes` - The new size of the filesystem in bytes
+/// * `workdir` - The seed working directory for temporary mounts
+pub fn shrink_filesystem(
+    part_path: &str,
+    fs: &str,
+    bytes: u64,
+    workdir: &str,
+) -> Result<(), SeedError> {
+    check_resizable(part_path, fs, true)?;
+    info!(
+        "Shrinking {} filesystem on {} to {} bytes",
+        fs, part_path, bytes
+    );
+
+    match fs {
+        "ext2" | "ext3" | "ext4" => {
+            check_ext_filesystem(part_path)?;
+            run_resize_command(
+                part_path,
+                Command::new("resize2fs")
+                    .arg(part_path)
+                    .arg(format!("{}K", bytes / 1024)),
+            )
+        }
+        "btrfs" => with_temporary_mount(part_path, workdir, MountFlags::empty(), |mount_point| {
+            run_resize_command(
+                part_path,
+                Command::new("btrfs")
+                    .arg("filesystem")
+                    .arg("resize")
+                    .arg(bytes.to_string())
+                    .arg(mount_point),
+            )
+        }),
+        //ntfsresize needs to be forced twice to not ask for confirmation
+        "ntfs" => run_resize_command(
+            part_path,
+            Command::new("ntfsresize")
+                .arg("-f")
+                .arg("-f")
+                .arg("-s")
+                .arg(bytes.to_string())
+                .arg(part_path),
+        ),
+        other => Err(SeedError::resize(
+            part_path,
+            format!("Filesystem '{}' can not be shrunk", other),
+        )),
+    }
+}
+
+/// Grows the filesystem on the partition to fill the whole partition,
+/// this has to happen after the partition has been grown and committed
+/// # Arguments
+/// * `part_path` - The path to the partition
+/// * `fs` - The filesystem on the partition
+/// * `workdir` - The seed working directory for temporary mounts
+pub fn grow_filesystem(part_path: &str, fs: &str, workdir: &str) -> Result<(), SeedError> {
+    check_resizable(part_path, fs, false)?;
+    info!(
+        "Growing {} filesystem on {} to partition size",
+        fs, part_path
+    );
+
+    match fs {
+        "ext2" | "ext3" | "ext4" => {
+            check_ext_filesystem(part_path)?;
+            run_resize_command(part_path, Command::new("resize2fs").arg(part_path))
+        }
+        "btrfs" => with_temporary_mount(part_path, workdir, MountFlags::empty(), |mount_point| {
+            run_resize_command(
+                part_path,
+                Command::new("btrfs")
+                    .arg("filesystem")
+                    .arg("resize")
+                    .arg("max")
+                    .arg(mount_point),
+            )
+        }),
+        "xfs" => with_temporary_mount(part_path, workdir, MountFlags::empty(), |mount_point| {
+            run_resize_command(part_path, Command::new("xfs_growfs").arg(mount_point))
+        }),
+        "ntfs" => run_resize_command(
+            part_path,
+            Command::new("ntfsresize")
+                .arg("-f")
+                .arg("-f")
+                .arg(part_path),
+        ),
+        other => Err(SeedError::resize(
+            part_path,
+            format!("Resizing filesystem '{}' is not supported", other),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_partx_geom_converts_sectors() {
+        assert_eq!(
+            parse_partx_geom(" 2048 1050623\n", 512),
+            Some((2048, 1050623))
+        );
+        assert_eq!(
+            parse_partx_geom(" 2048 1050623\n", 4096),
+            Some((256, 131327))
+        );
+        assert_eq!(parse_partx_geom("", 512), None);
+    }
+}
@@ -0,0 +1,421 @@
+use super::util::get_blkid_tag;
+use crate::conf::seed::*;
+use crate::error::SeedError;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// What is currently on a block device that seed would overwrite
+pub struct DeviceUsage {
+    pub device: String,
+    pub fs: Option<String>,
+    pub label: Option<String>,
+    pub mounts: Vec<String>,
+    pub swap: bool,
+    pub holders: Vec<String>,
+}
+
+impl DeviceUsage {
+    /// Returns if the device is in active use by the running system
+    pub fn is_active(&self) -> bool {
+        !self.mounts.is_empty() || self.swap || !self.holders.is_empty()
+    }
+}
+
+/// The summary of a disk seed is about to write to
+pub struct DiskReport {
+    pub disk: String,
+    pub table: Option<String>,
+    pub is_root: bool,
+    pub devices: Vec<DeviceUsage>,
+}
+
+impl DiskReport {
+    /// Returns if the disk contains anything that would get lost
+    pub fn has_data(&self) -> bool {
+        self.table.is_some() || self.devices.iter().any(|d| d.fs.is_some() || d.is_active())
+    }
+}
+
+/// Returns the kernel name of the block device (e.g. 'sda' for '/dev/disk/by-id/ata-...')
+/// # Arguments
+/// * `path` - The path to the block device
+pub fn get_block_name(path: &str) -> Result<String, SeedError> {
+    let canonical = std::fs::canonicalize(path).map_err(|e| SeedError::io(path, e))?;
+    match canonical.file_name() {
+        Some(n) => Ok(n.to_string_lossy().into_owned()),
+        None => Err(SeedError::Safety {
+            disk: Some(path.to_owned()),
+            msg: "Not a block device".to_owned(),
+        }),
+    }
+}
+
+/// Returns the names of the entries of a sysfs directory, empty if it does not exist
+/// # Arguments
+/// * `dir` - The directory to list
+fn list_sysfs_dir(dir: &Path) -> Vec<String> {
+    match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Returns the kernel names of the partitions of the disk
+/// # Arguments
+/// * `disk_name` - The kernel name of the disk
+pub fn get_partition_names(disk_name: &str) -> Vec<String> {
+    let disk_dir = Path::new("/sys/class/block").join(disk_name);
+    let mut res: Vec<String> = list_sysfs_dir(&disk_dir)
+        .into_iter()
+        .filter(|n| disk_dir.join(n).join("partition").is_file())
+        .collect();
+    res.sort();
+    res
+}
+
+/// Returns the mounts of the running system
+/// # Returns
+/// The kernel name of the mounted device and the mount point
+pub fn get_mounts() -> Result<Vec<(String, String)>, SeedError> {
+    let mut res = Vec::new();
+
+    let mounts = std::fs::read_to_string("/proc/self/mounts")
+        .map_err(|e| SeedError::io("/proc/self/mounts", e))?;
+    for line in mounts.lines() {
+        let mut fields = line.split_whitespace();
+        let (source, target) = match (fields.next(), fields.next()) {
+            (Some(s), Some(t)) => (s, t),
+            _ => continue,
+        };
+
+        //Only block devices are of interest
+        if !source.starts_with('/') {
+            continue;
+        }
+
+        match get_block_name(source) {
+            Ok(name) => res.push((name, target.replace("\\040", " "))),
+            Err(_) => continue,
+        }
+    }
+
+    Ok(res)
+}
+
+/// Returns the kernel names of the active swap devices
+fn get_swaps() -> Result<Vec<String>, SeedError> {
+    Ok(std::fs::read_to_string("/proc/swaps")
+        .map_err(|e| SeedError::io("/proc/swaps", e))?
+        .lines()
+        .skip(1)
+        .filter_map(|l| l.split_whitespace().next())
+        .filter_map(|d| get_block_name(d).ok())
+        .collect())
+}
+
+/// Resolves a sysfs block device to the disks it resides on
+/// Follows partitions to their disk and device mapper / md devices to their slaves
+/// # Arguments
+/// * `sysfs_path` - The canonical sysfs path of the block device
+fn resolve_disks(sysfs_path: &Path) -> Vec<String> {
+    if sysfs_path.join("partition").is_file() {
+        return match sysfs_path.parent().and_then(|p| p.file_name()) {
+            Some(n) => vec![n.to_string_lossy().into_owned()],
+            None => Vec::new(),
+        };
+    }
+
+    let slaves = list_sysfs_dir(&sysfs_path.join("slaves"));
+    if slaves.is_empty() {
+        return match sysfs_path.file_name() {
+            Some(n) => vec![n.to_string_lossy().into_owned()],
+            None => Vec::new(),
+        };
+    }
+
+    slaves
+        .iter()
+        .filter_map(|s| std::fs::canonicalize(Path::new("/sys/class/block").join(s)).ok())
+        .flat_map(|p| resolve_disks(&p))
+        .collect()
+}
+
+/// Finds the mount of the running root filesystem in the contents of /proc/self/mountinfo
+/// # Arguments
+/// * `mountinfo` - The contents of /proc/self/mountinfo
+/// # Returns
+/// The device number ('major:minor') and the source of the root mount, the last one mounted over '/'
+fn parse_root_mount(mountinfo: &str) -> Option<(String, String)> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            //The optional fields end with a '-', the filesystem type and the source follow
+            let separator = fields.iter().position(|f| *f == "-")?;
+            match (fields.get(2), fields.get(4), fields.get(separator + 2)) {
+                (Some(dev), Some(&"/"), Some(source)) => {
+                    Some((dev.to_string(), source.replace("\\040", " ")))
+                }
+                _ => None,
+            }
+        })
+        .last()
+}
+
+/// Returns the kernel names of the disks the running root filesystem resides on
+/// The device number of '/' is not enough: btrfs and overlays report anonymous device numbers
+pub fn get_root_disks() -> Result<Vec<String>, SeedError> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")
+        .map_err(|e| SeedError::io("/proc/self/mountinfo", e))?;
+    let (dev, source) = match parse_root_mount(&mountinfo) {
+        Some(m) => m,
+        None => return Ok(Vec::new()),
+    };
+
+    //Prefer the block device the root got mounted from, then its device number
+    let sysfs_path = match source.starts_with('/') {
+        true => match get_block_name(&source) {
+            Ok(name) => Path::new("/sys/class/block").join(name),
+            Err(_) => PathBuf::from(format!("/sys/dev/block/{}", dev)),
+        },
+        false => PathBuf::from(format!("/sys/dev/block/{}", dev)),
+    };
+
+    //Roots on virtual filesystems (e.g. the overlay of a live system) have no disk
+    match std::fs::canonicalize(&sysfs_path) {
+        Ok(p) => Ok(resolve_disks(&p)),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+/// Collects what is on the disk
+/// # Arguments
+/// * `d_conf` - The disk to inspect
+/// * `root_disks` - The disks of the running root filesystem
+fn get_disk_report(d_conf: &DiskConf, root_disks: &[String]) -> Result<DiskReport, SeedError> {
+    let disk_name = get_block_name(&d_conf.path)?;
+    let mounts = get_mounts()?;
+    let swaps = get_swaps()?;
+
+    let mut names = vec![disk_name.clone()];
+    names.append(&mut get_partition_names(&disk_name));
+
+    let mut devices = Vec::new();
+    for name in names {
+        let device = format!("/dev/{}", name);
+        devices.push(DeviceUsage {
+            fs: get_blkid_tag(&device, "TYPE")?,
+            label: get_blkid_tag(&device, "LABEL")?,
+            mounts: mounts
+                .iter()
+                .filter(|(source, _)| *source == name)
+                .map(|(_, target)| target.clone())
+                .collect(),
+            swap: swaps.contains(&name),
+            holders: list_sysfs_dir(&Path::new("/sys/class/block").join(&name).join("holders")),
+            device,
+        });
+    }
+
+    Ok(DiskReport {
+        disk: d_conf.path.clone(),
+        table: get_blkid_tag(&format!("/dev/{}", disk_name), "PTTYPE")?,
+        is_root: root_disks.contains(&disk_name),
+        devices,
+    })
+}
+
+/// Returns if seed is going to write to the disk
+/// # Arguments
+/// * `d_conf` - The disk to check
+fn is_disk_modified(d_conf: &DiskConf) -> bool {
+    match d_conf.action {
+        DiskAction::New => true,
+        DiskAction::Alter => d_conf
+            .partitions
+            .iter()
+            .any(|p| p.action != PartAction::Keep),
+        DiskAction::Keep | DiskAction::Locked => false,
+    }
+}
+
+/// Logs the summary of the disk
+/// # Arguments
+/// * `report` - The report to log
+fn log_disk_report(report: &DiskReport) {
+    warn!(
+        "{}: partition table: {}{}",
+        report.disk,
+        report.table.as_deref().unwrap_or("none"),
+        if report.is_root {
+            " (holds the running root filesystem)"
+        } else {
+            ""
+        }
+    );
+
+    for device in &report.devices {
+        if device.fs.is_none() && !device.is_active() {
+            continue;
+        }
+
+        let mut info = vec![format!(
+            "filesystem: {}",
+            device.fs.as_deref().unwrap_or("none")
+        )];
+        match &device.label {
+            Some(l) => info.push(format!("label: {}", l)),
+            None => (),
+        }
+        if !device.mounts.is_empty() {
+            info.push(format!("mounted at {}", device.mounts.join(", ")));
+        }
+        if device.swap {
+            info.push("active swap".to_owned());
+        }
+        if !device.holders.is_empty() {
+            info.push(format!(
+                "in use by {} (LVM / RAID)",
+                device.holders.join(", ")
+            ));
+        }
+
+        warn!("  {}: {}", device.device, info.join(", "));
+    }
+}
+
+/// Asks the user to confirm the destruction of data
+fn ask_confirmation() -> Result<bool, SeedError> {
+    eprint!("Data on the disks above will be destroyed. Type 'yes' to continue: ");
+    io::stderr().flush()?;
+
+    let mut answer = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(|e| SeedError::io("Failed to read the confirmation", e))?;
+
+    Ok(answer.trim() == "yes")
+}
+
+/// Checks the disks seed is about to write to for data and active use
+/// Refuses to touch the disk of the running root filesystem and disks the running system uses,
+/// asks for confirmation if data would get destroyed
+/// # Arguments
+/// * `conf` - The seed config
+/// * `yes_destroy` - Do not ask for confirmation
+/// * `ask` - If the confirmation may be asked for on the terminal, false for the daemon
+pub fn check_disks(conf: &SeedConf, yes_destroy: bool, ask: bool) -> Result<(), SeedError> {
+    let root_disks = get_root_disks()?;
+
+    let mut reports = Vec::new();
+    for d_conf in conf.disks.iter().filter(|d| is_disk_modified(d)) {
+        let report = get_disk_report(d_conf, &root_disks)?;
+
+        if report.is_root {
+            return Err(SeedError::Safety {
+                disk: Some(report.disk),
+                msg: "It holds the running root filesystem".to_owned(),
+            });
+        }
+
+        //Changing devices the running system uses corrupts them, confirming does not make it safe
+        match report.devices.iter().find(|d| d.is_active()) {
+            Some(device) => {
+                log_disk_report(&report);
+                return Err(SeedError::Safety {
+                    disk: Some(report.disk.clone()),
+                    msg: format!(
+                        "{} is in use (mounted, swap or LVM / RAID), release it first",
+                        device.device
+                    ),
+                });
+            }
+            None => (),
+        }
+
+        if report.has_data() {
+            reports.push(report);
+        }
+    }
+
+    if reports.is_empty() {
+        return Ok(());
+    }
+
+    warn!("The following disks contain data that will be modified:");
+    for report in &reports {
+        log_disk_report(report);
+    }
+
+    //A dry run does not touch anything
+    if conf.dry_run || yes_destroy {
+        return Ok(());
+    }
+
+    if !ask {
+        return Err(SeedError::Safety {
+            disk: None,
+            msg: "Refusing to destroy data without confirmation, 'start' needs 'yes_destroy'"
+                .to_owned(),
+        });
+    }
+    if !io::stdin().is_terminal() {
+        return Err(SeedError::Safety {
+            disk: None,
+            msg: "Refusing to destroy data without confirmation, use '--yes-destroy'".to_owned(),
+        });
+    }
+
+    match ask_confirmation()? {
+        true => Ok(()),
+        false => Err(SeedError::Safety {
+            disk: None,
+            msg: "Aborted by user".to_owned(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_mount_comes_from_the_source() {
+        //btrfs reports an anonymous device number, only the source names the partition
+        let mountinfo = "\
+22 1 0:21 /@ / rw,relatime shared:1 - btrfs /dev/nvme0n1p2 rw,ssd,subvol=/@
+23 22 0:5 / /dev rw,nosuid shared:2 - devtmpfs devtmpfs rw
+24 22 0:21 /@home /home rw,relatime shared:3 - btrfs /dev/nvme0n1p2 rw,subvol=/@home
+";
+        assert_eq!(
+            parse_root_mount(mountinfo),
+            Some(("0:21".to_owned(), "/dev/nvme0n1p2".to_owned()))
+        );
+    }
+
+    #[test]
+    fn last_root_mount_wins() {
+        //A live system mounts an overlay over its initial root, without optional fields
+        let mountinfo = "\
+1 1 8:1 / / rw - ext4 /dev/sda1 rw
+30 1 0:32 / / rw,relatime - overlay overlay rw,lowerdir=/run/lower
+";
+        assert_eq!(
+            parse_root_mount(mountinfo),
+            Some(("0:32".to_owned(), "overlay".to_owned()))
+        );
+    }
+
+    #[test]
+    fn missing_root_mount_is_none() {
+        assert_eq!(parse_root_mount(""), None);
+        assert_eq!(
+            parse_root_mount("23 22 0:5 / /dev rw - devtmpfs devtmpfs rw\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use super::safety::get_block_name;
+use crate::error::SeedError;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// How long to wait for the device node of a new partition
+const PARTITION_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to wait between checks for a device node
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Returns if the path is a block device, disk images have no partition nodes to wait for
+/// # Arguments
+/// * `path` - The path to check
+pub fn is_block_device(path: &str) -> bool {
+    match std::fs::metadata(path) {
+        Ok(m) => m.file_type().is_block_device(),
+        Err(_) => false,
+    }
+}
+
+/// Runs a helper that is not required for seed to work, failing only gets logged
+/// # Arguments
+/// * `command` - The command to run
+fn run_helper(command: &mut Command) {
+    debug!("Running {:?}", command);
+    match command.output() {
+        Ok(output) => {
+            if !output.status.success() {
+                warn!(
+                    "{:?} failed: {}",
+                    command.get_program(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+        }
+        Err(e) => warn!("Failed to run {:?}: {}", command.get_program(), e),
+    }
+}
+
+/// Makes the kernel re-read the partition table of the disk and waits for udev to create the nodes
+/// # Arguments
+/// * `disk_path` - The path to the disk
+pub fn reread_partitions(disk_path: &str) {
+    //partx updates the partitions one by one, this also works if some of them are in use
+    run_helper(Command::new("partx").arg("-u").arg(disk_path));
+    run_helper(
+        Command::new("udevadm")
+            .arg("settle")
+            .arg(format!("--timeout={}", PARTITION_TIMEOUT.as_secs())),
+    );
+}
+
+/// Returns the size of a block device as reported by the kernel
+/// # Arguments
+/// * `path` - The path to the block device
+/// # Returns
+/// The size in bytes, None if the kernel does not know the device
+fn get_sysfs_size(path: &str) -> Option<u64> {
+    let name = get_block_name(path).ok()?;
+    let size =
+        std::fs::read_to_string(Path::new("/sys/class/block").join(name).join("size")).ok()?;
+
+    //sysfs always reports the size in 512 byte sectors
+    size.trim().parse::<u64>().ok().map(|s| s * 512)
+}
+
+/// Waits until the device node of a partition exists and has the expected size
+/// # Arguments
+/// * `disk_path` - The path to the disk the partition resides on (for context)
+/// * `index` - The index of the partition (for context)
+/// * `part_path` - The path to the partition
+/// * `bytes` - The size the partition should have
+pub fn wait_for_partition(
+    disk_path: &str,
+    index: usize,
+    part_path: &str,
+    bytes: u64,
+) -> Result<(), SeedError> {
+    let start = Instant::now();
+
+    loop {
+        let size = match is_block_device(part_path) {
+            true => get_sysfs_size(part_path),
+            false => None,
+        };
+
+        match size {
+            Some(s) if s == bytes => {
+                debug!("{} is ready ({} bytes)", part_path, s);
+                return Ok(());
+            }
+            _ => (),
+        }
+
+        if start.elapsed() >= PARTITION_TIMEOUT {
+            let msg = match size {
+                Some(s) => format!("{} has {} bytes, expected {} bytes", part_path, s, bytes),
+                None => format!(
+                    "{} did not appear within {} seconds",
+                    part_path,
+                    PARTITION_TIMEOUT.as_secs()
+                ),
+            };
+            return Err(SeedError::parted(disk_path, Some(index), msg));
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -1,5 +1,10 @@
+use super::safety::{get_block_name, get_partition_names};
+use crate::conf::disk::{get_part_separator, DiskConf};
 use crate::conf::part::*;
+use crate::error::SeedError;
 use libparted::*;
+use std::path::Path;
+use std::process::Command;
 
 ///	Converts the provided byte count to sector count by aligning to next sector
 /// If the count doesn't fit perfectly into the sector, the sector count will be higher
@@ -18,30 +23,351 @@ pub fn bytes_to_sectors(bytes: u64, sector_size: u64) -> u64 {
     }
 }
 
-/// Determines the next possible start for a new partition in sector number
+/// Looks up the kernel name of a partition in sysfs
 /// # Arguments
-/// * `p_disk` - The disk to align to
-pub fn get_next_possible_start<'a>(p_disk: &Disk<'a>) -> Option<i64> {
-    let last_part_num = match p_disk.get_last_partition_num() {
-        Some(n) => n,
-        None => return Some(0),
-    };
+/// * `disk_path` - The path to the disk the partition resides on
+/// * `index` - The index of the partition
+/// # Returns
+/// The kernel name of the partition, None if the kernel does not know it (yet)
+fn get_sysfs_part_name(disk_path: &str, index: usize) -> Option<String> {
+    let disk_name = get_block_name(disk_path).ok()?;
 
-    let part = p_disk.get_partition(last_part_num)?;
+    get_partition_names(&disk_name).into_iter().find(|name| {
+        std::fs::read_to_string(Path::new("/sys/class/block").join(name).join("partition"))
+            .map(|n| n.trim() == index.to_string())
+            .unwrap_or(false)
+    })
+}
+
+/// Returns the device path of a partition on the provided disk
+/// Partitions of udev links ('/dev/disk/by-id/...') are addressed by their udev link,
+/// the others by the name the kernel gave them
+/// # Arguments
+/// * `disk_path` - The path to the disk the partition resides on
+/// * `index` - The index of the partition
+pub fn get_part_path(disk_path: &str, index: usize) -> String {
+    let separator = get_part_separator(disk_path);
+    if separator == "-part" {
+        return format!("{}{}{}", disk_path, separator, index);
+    }
+
+    match get_sysfs_part_name(disk_path, index) {
+        Some(name) => format!("/dev/{}", name),
+        //The partition does not exist yet, the kernel will follow the naming scheme
+        None => format!("{}{}{}", disk_path, separator, index),
+    }
+}
+
+/// Returns the device the filesystem of a partition lives on,
+/// the mapping of encrypted partitions or the partition itself
+/// # Arguments
+/// * `disk_path` - The path to the disk the partition resides on
+/// * `p_conf` - The partition
+pub fn get_fs_path(disk_path: &str, p_conf: &PartConf) -> String {
+    match &p_conf.encrypt {
+        Some(e) => format!("/dev/mapper/{}", e.name),
+        None => get_part_path(disk_path, p_conf.index),
+    }
+}
+
+/// Queries a tag (UUID, LABEL, TYPE...) of a block device using blkid
+/// # Arguments
+/// * `device` - The block device to query
+/// * `tag` - The tag to query
+/// # Returns
+/// The value of the tag or None if the device doesn't have it
+pub fn get_blkid_tag(device: &str, tag: &str) -> Result<Option<String>, SeedError> {
+    let output = Command::new("blkid")
+        .arg("-s")
+        .arg(tag)
+        .arg("-o")
+        .arg("value")
+        .arg(device)
+        .output()
+        .map_err(|e| SeedError::io("Failed to run blkid", e))?;
+
+    //blkid exits with 2 if the tag could not be found
+    match output.status.code() {
+        Some(0) => (),
+        Some(2) => return Ok(None),
+        _ => {
+            let err_msg = String::from_utf8_lossy(&output.stderr).replace("\n", "");
+            return Err(SeedError::command(
+                &format!("blkid -s {} {}", tag, device),
+                err_msg,
+            ));
+        }
+    }
+
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    match value.is_empty() {
+        true => Ok(None),
+        false => Ok(Some(value)),
+    }
+}
+
+/// The space on a disk partition sizes get resolved against
+pub struct DiskSpace {
+    /// The sector size of the disk
+    pub sector_size: u64,
+    /// The total amount of sectors on the disk
+    pub total_sectors: i64,
+    /// The sectors that are not claimed by the partitions processed so far
+    pub free_sectors: i64,
+    /// The boundary new partitions start at, in sectors
+    pub alignment: i64,
+}
+
+/// The default boundary partitions get aligned to
+pub const DEFAULT_ALIGNMENT_BYTES: u64 = 1024 * 1024;
+/// The size of the partition entries of a GPT
+const GPT_ENTRIES_BYTES: u64 = 16384;
+
+/// Determines the boundary new partitions on the device get aligned to
+/// # Arguments
+/// * `p_dev` - The device to align to
+/// * `d_conf` - The disk config, its 'alignment' takes precedence
+/// # Returns
+/// The alignment in sectors
+pub fn get_alignment_sectors(p_dev: &Device, d_conf: &DiskConf) -> i64 {
+    let sector_size = p_dev.sector_size();
+
+    match &d_conf.alignment {
+        Some(PartSize::Size(count, unit)) => {
+            let bytes =
+                data_size_bytes(*count, unit).expect("Invalid alignment, was validate() called?");
+            return bytes_to_sectors(bytes, sector_size).max(1) as i64;
+        }
+        _ => (),
+    }
+
+    let default = bytes_to_sectors(DEFAULT_ALIGNMENT_BYTES, sector_size) as i64;
 
-    Some(part.geom_end() + 1)
+    //Devices with a larger optimal I/O size get aligned to it, if it stays compatible with 1 MiB
+    match p_dev.get_optimum_alignment() {
+        Some(a)
+            if a.grain_size() > default
+                && (a.grain_size() as u64).is_multiple_of(default as u64) =>
+        {
+            a.grain_size()
+        }
+        _ => default,
+    }
+}
+
+/// Determines the sectors partitions can occupy, leaving out the partition table itself
+/// # Arguments
+/// * `is_gpt` - If the disk has a GPT, else it has an msdos table
+/// * `space` - The space available on the disk
+/// # Returns
+/// The first and the last usable sector
+fn get_usable_range(is_gpt: bool, space: &DiskSpace) -> (i64, i64) {
+    match is_gpt {
+        //The protective MBR, the header and the entries at the start, the backup entries and header at the end
+        true => {
+            let entries = bytes_to_sectors(GPT_ENTRIES_BYTES, space.sector_size) as i64;
+            (2 + entries, space.total_sectors - 2 - entries)
+        }
+        //The MBR
+        false => (1, space.total_sectors - 1),
+    }
+}
+
+/// Rounds the sector up to the next multiple of the alignment
+/// # Arguments
+/// * `sector` - The sector to align
+/// * `alignment` - The alignment in sectors
+fn align_up(sector: i64, alignment: i64) -> i64 {
+    //Sectors and alignments are never negative
+    (sector as u64).div_ceil(alignment as u64) as i64 * alignment
+}
+
+/// Determines where the next new partition can be placed: the largest free region
+/// after aligning its start and leaving out the areas of the partition table
+/// # Arguments
+/// * `p_disk` - The disk to place the partition on
+/// * `space` - The space available on the disk
+/// * `logical` - If the partition is a logical partition, which is placed inside the extended partition
+/// # Returns
+/// The aligned start sector and the last sector of the region, None if there is no free space
+pub fn get_next_possible_start<'a>(
+    p_disk: &Disk<'a>,
+    space: &DiskSpace,
+    logical: bool,
+) -> Option<(i64, i64)> {
+    let is_gpt = p_disk.get_disk_type_name() == Some(b"gpt".as_slice());
+    let (first_usable, last_usable) = get_usable_range(is_gpt, space);
+    let extended = p_disk
+        .get_extended_partition()
+        .map(|p| (p.geom_start(), p.geom_end()));
+
+    let mut best: Option<(i64, i64)> = None;
+    for p_part in p_disk.parts() {
+        if !is_free_region(&p_part) {
+            continue;
+        }
+
+        //The free space inside the extended partition is only available to logical partitions
+        let in_extended = match extended {
+            Some((s, e)) => p_part.geom_start() >= s && p_part.geom_end() <= e,
+            None => false,
+        };
+        if in_extended != logical {
+            continue;
+        }
+
+        let start = align_up(p_part.geom_start().max(first_usable), space.alignment);
+        let end = p_part.geom_end().min(last_usable);
+        if end < start {
+            continue;
+        }
+
+        match best {
+            Some((s, e)) if e - s >= end - start => (),
+            _ => best = Some((start, end)),
+        }
+    }
+
+    best
+}
+
+/// Checks if the libparted partition is a region of free space
+/// # Arguments
+/// * `p_part` - The partition to check
+pub fn is_free_region(p_part: &Partition) -> bool {
+    p_part.type_get_name() == "free"
+}
+
+/// Determines the last sector a partition starting at the provided sector can extend to
+/// # Arguments
+/// * `p_disk` - The disk to search
+/// * `sector` - The sector the partition starts at, can be inside an existing partition
+/// # Returns
+/// The last sector of the free region at or directly after `sector`
+pub fn get_max_end<'a>(p_disk: &Disk<'a>, sector: i64) -> Option<i64> {
+    let mut end: Option<i64> = None;
+
+    for p_part in p_disk.parts() {
+        //Skip regions that end before the sector
+        if p_part.geom_end() < sector {
+            continue;
+        }
+
+        match end {
+            //The region containing the sector
+            None => {
+                if p_part.geom_start() > sector {
+                    return None;
+                }
+                end = Some(p_part.geom_end());
+                if is_free_region(&p_part) {
+                    return end;
+                }
+            }
+            //The region after an existing partition: only free space can be claimed
+            Some(e) => {
+                if is_free_region(&p_part) && p_part.geom_start() == e + 1 {
+                    return Some(p_part.geom_end());
+                }
+                return end;
+            }
+        }
+    }
+
+    end
 }
 
 /// Convert the supplied PartSize to sectors
 /// # Arguments
+/// * `p_disk` - The disk the partition resides on
 /// * `p_size` - The size enum to convert
-/// * `sector_size` - The sector size to use
-pub fn get_part_size_sectors(p_size: &PartSize, sector_size: u64) -> i64 {
-    bytes_to_sectors(
-        match p_size {
-            PartSize::Size(count, unit) => data_size_bytes(*count as i64, unit) as u64,
-            _ => 1,
+/// * `space` - The space available on the disk
+/// * `start_sector` - The sector the partition starts at
+/// # Returns
+/// The size in sectors, else why the size can't be determined
+pub fn get_part_size_sectors<'a>(
+    p_disk: &Disk<'a>,
+    p_size: &PartSize,
+    space: &DiskSpace,
+    start_sector: i64,
+) -> Result<i64, String> {
+    match p_size {
+        PartSize::Size(count, unit) => Ok(bytes_to_sectors(
+            data_size_bytes(*count, unit).expect("Invalid size, was validate() called?"),
+            space.sector_size,
+        ) as i64),
+        PartSize::PercentTotal(p) => Ok((space.total_sectors as f64 * *p as f64) as i64),
+        PartSize::PercentFree(p) => Ok((space.free_sectors as f64 * *p as f64) as i64),
+        PartSize::Total(PartTotal::Max) => match get_max_end(p_disk, start_sector) {
+            Some(end) => Ok(end - start_sector + 1),
+            None => Err(format!(
+                "No free space available at sector {}",
+                start_sector
+            )),
         },
-        sector_size,
-    ) as i64
+        PartSize::Total(PartTotal::Min) => {
+            Err("Size 'min' is only available when resizing".to_owned())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::disk::get_part_index;
+
+    //The disks do not exist, the paths follow the naming scheme of the kernel and udev
+    const NAMING: &[(&str, &str)] = &[
+        ("/dev/sdx", "/dev/sdx2"),
+        ("/dev/nvme9n1", "/dev/nvme9n1p2"),
+        ("/dev/mmcblk9", "/dev/mmcblk9p2"),
+        ("/dev/loop99", "/dev/loop99p2"),
+        (
+            "/dev/disk/by-id/ata-SEED_TEST_DISK",
+            "/dev/disk/by-id/ata-SEED_TEST_DISK-part2",
+        ),
+    ];
+
+    #[test]
+    fn get_part_path_follows_naming_scheme() {
+        for (disk, part) in NAMING {
+            assert_eq!(get_part_path(disk, 2), *part);
+        }
+    }
+
+    #[test]
+    fn get_part_index_reverses_part_path() {
+        for (disk, part) in NAMING {
+            assert_eq!(get_part_index(disk, part), Some(2));
+        }
+        assert_eq!(get_part_index("/dev/sdx", "/dev/sdxa1"), None);
+        assert_eq!(get_part_index("/dev/nvme9n1", "/dev/nvme9n10"), None);
+    }
+
+    fn space(sector_size: u64, total_sectors: i64) -> DiskSpace {
+        DiskSpace {
+            sector_size,
+            total_sectors,
+            free_sectors: total_sectors,
+            alignment: bytes_to_sectors(DEFAULT_ALIGNMENT_BYTES, sector_size) as i64,
+        }
+    }
+
+    #[test]
+    fn usable_range_leaves_out_the_table() {
+        //GPT: 128 entries of 128 bytes behind the protective MBR and the header, mirrored at the end
+        assert_eq!(get_usable_range(true, &space(512, 2097152)), (34, 2097118));
+        assert_eq!(get_usable_range(true, &space(4096, 262144)), (6, 262138));
+        //msdos: only the MBR
+        assert_eq!(get_usable_range(false, &space(512, 2097152)), (1, 2097151));
+    }
+
+    #[test]
+    fn sectors_get_aligned_up() {
+        assert_eq!(align_up(0, 2048), 0);
+        assert_eq!(align_up(34, 2048), 2048);
+        assert_eq!(align_up(2048, 2048), 2048);
+        assert_eq!(align_up(2049, 2048), 4096);
+        assert_eq!(align_up(7, 1), 7);
+    }
 }
@@ -0,0 +1,237 @@
+use crate::conf::installfile::ParseError;
+use crate::conf::validate::ValidationError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// The errors seed can run into, grouped by what failed
+#[derive(Debug)]
+pub enum SeedError {
+    /// The installfile could not be parsed
+    Parse(ParseError),
+    /// The installfile is invalid
+    Validation(ValidationError),
+    /// seed refused to touch a disk
+    Safety { disk: Option<String>, msg: String },
+    /// The journal could not be read, written or applied
+    Journal(String),
+    /// The installation got cancelled
+    Cancelled,
+    /// A partition table operation failed
+    Parted {
+        disk: String,
+        partition: Option<usize>,
+        msg: String,
+    },
+    /// Creating a filesystem failed
+    Mkfs { device: String, stderr: String },
+    /// Resizing a filesystem failed
+    Resize { device: String, msg: String },
+    /// Mounting or unmounting a filesystem failed
+    Mount { target: String, msg: String },
+    /// An external tool failed
+    Command { command: String, stderr: String },
+    /// Leaf could not be configured
+    LeafConfig(String),
+    /// Leaf failed to install the packages
+    LeafInstall(String),
+    /// A command inside the target system failed
+    Chroot { command: String, stderr: String },
+    /// Configuring the target system failed
+    Target { what: String, msg: String },
+    /// Any other I/O error
+    Io { context: String, error: io::Error },
+}
+
+impl SeedError {
+    /// Creates an error for a failed partition table operation
+    /// # Arguments
+    /// * `disk` - The path to the disk
+    /// * `partition` - The index of the partition, if the operation concerned one
+    /// * `msg` - What went wrong
+    pub fn parted<T: ToString>(disk: &str, partition: Option<usize>, msg: T) -> SeedError {
+        SeedError::Parted {
+            disk: disk.to_owned(),
+            partition,
+            msg: msg.to_string(),
+        }
+    }
+
+    /// Creates an error for a failed filesystem resize
+    /// # Arguments
+    /// * `device` - The resized device
+    /// * `msg` - What went wrong
+    pub fn resize<T: ToString>(device: &str, msg: T) -> SeedError {
+        SeedError::Resize {
+            device: device.to_owned(),
+            msg: msg.to_string(),
+        }
+    }
+
+    /// Creates an error for a failed mount or unmount
+    /// # Arguments
+    /// * `target` - The mount point
+    /// * `msg` - What went wrong
+    pub fn mount<T: ToString>(target: &str, msg: T) -> SeedError {
+        SeedError::Mount {
+            target: target.to_owned(),
+            msg: msg.to_string(),
+        }
+    }
+
+    /// Creates an error for a failed external tool
+    /// # Arguments
+    /// * `command` - The command that failed
+    /// * `stderr` - The error output of the command
+    pub fn command<T: ToString>(command: &str, stderr: T) -> SeedError {
+        SeedError::Command {
+            command: command.to_owned(),
+            stderr: stderr.to_string(),
+        }
+    }
+
+    /// Adds context to an I/O error
+    /// # Arguments
+    /// * `context` - What seed was doing
+    /// * `error` - The I/O error
+    pub fn io(context: &str, error: io::Error) -> SeedError {
+        SeedError::Io {
+            context: context.to_owned(),
+            error,
+        }
+    }
+
+    /// Returns the exit code seed exits with for this error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SeedError::Io { .. } => 1,
+            SeedError::Parse(_) => 3,
+            SeedError::Validation(_) => 4,
+            SeedError::Safety { .. } => 5,
+            SeedError::Journal(_) => 6,
+            SeedError::Cancelled => 7,
+            SeedError::Parted { .. } => 10,
+            SeedError::Mkfs { .. } => 11,
+            SeedError::Resize { .. } => 12,
+            SeedError::Mount { .. } => 13,
+            SeedError::Command { .. } => 14,
+            SeedError::LeafConfig(_) => 20,
+            SeedError::LeafInstall(_) => 21,
+            SeedError::Chroot { .. } => 30,
+            SeedError::Target { .. } => 31,
+        }
+    }
+}
+
+impl Error for SeedError {}
+
+impl fmt::Display for SeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SeedError::Parse(e) => write!(f, "{}", e),
+            SeedError::Validation(e) => write!(f, "{}", e),
+            SeedError::Safety { disk, msg } => match disk {
+                Some(d) => write!(f, "Refusing to modify {}: {}", d, msg),
+                None => write!(f, "{}", msg),
+            },
+            SeedError::Journal(msg) => write!(f, "Journal: {}", msg),
+            SeedError::Cancelled => write!(f, "The installation got cancelled"),
+            SeedError::Parted {
+                disk,
+                partition,
+                msg,
+            } => match partition {
+                Some(p) => write!(f, "Partition {} on {}: {}", p, disk, msg),
+                None => write!(f, "Disk {}: {}", disk, msg),
+            },
+            SeedError::Mkfs { device, stderr } => {
+                write!(f, "Failed to create filesystem on {}: {}", device, stderr)
+            }
+            SeedError::Resize { device, msg } => {
+                write!(f, "Failed to resize filesystem on {}: {}", device, msg)
+            }
+            SeedError::Mount { target, msg } => write!(f, "Mount point {}: {}", target, msg),
+            SeedError::Command { command, stderr } => write!(f, "'{}' failed: {}", command, stderr),
+            SeedError::LeafConfig(msg) => write!(f, "Leaf config error: {}", msg),
+            SeedError::LeafInstall(msg) => write!(f, "Leaf error: {}", msg),
+            SeedError::Chroot { command, stderr } => {
+                write!(f, "Command '{}' failed in chroot: {}", command, stderr)
+            }
+            SeedError::Target { what, msg } => write!(f, "Failed to configure {}: {}", what, msg),
+            SeedError::Io { context, error } => match context.is_empty() {
+                true => write!(f, "{}", error),
+                false => write!(f, "{}: {}", context, error),
+            },
+        }
+    }
+}
+
+impl From<io::Error> for SeedError {
+    fn from(error: io::Error) -> Self {
+        SeedError::Io {
+            context: String::new(),
+            error,
+        }
+    }
+}
+
+impl From<ParseError> for SeedError {
+    fn from(e: ParseError) -> Self {
+        SeedError::Parse(e)
+    }
+}
+
+impl From<ValidationError> for SeedError {
+    fn from(e: ValidationError) -> Self {
+        SeedError::Validation(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One error of every category
+    fn get_errors() -> Vec<SeedError> {
+        vec![
+            SeedError::Parse(ParseError::new("test", None, None, "parse")),
+            SeedError::Validation(ValidationError::new("test", "invalid")),
+            SeedError::Safety {
+                disk: None,
+                msg: String::new(),
+            },
+            SeedError::Journal(String::new()),
+            SeedError::Cancelled,
+            SeedError::parted("/dev/sdx", None, ""),
+            SeedError::Mkfs {
+                device: String::new(),
+                stderr: String::new(),
+            },
+            SeedError::resize("/dev/sdx1", ""),
+            SeedError::mount("/", ""),
+            SeedError::command("true", ""),
+            SeedError::LeafConfig(String::new()),
+            SeedError::LeafInstall(String::new()),
+            SeedError::Chroot {
+                command: String::new(),
+                stderr: String::new(),
+            },
+            SeedError::Target {
+                what: String::new(),
+                msg: String::new(),
+            },
+            SeedError::io("test", io::Error::other("io")),
+        ]
+    }
+
+    #[test]
+    fn exit_codes_are_distinct_per_category() {
+        let mut codes: Vec<i32> = get_errors().iter().map(|e| e.exit_code()).collect();
+        //0 is success, clap exits with 2 on invalid arguments
+        assert!(codes.iter().all(|c| *c != 0 && *c != 2));
+
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), get_errors().len());
+    }
+}
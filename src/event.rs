@@ -0,0 +1,179 @@
+use crate::conf::validate::ValidationError;
+use crate::error::SeedError;
+use crate::journal::Stage;
+use serde::Serialize;
+use std::fmt;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::sync::Mutex;
+
+/// A progress event for frontends, emitted as a single JSON line
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    StageStarted {
+        stage: Stage,
+        percent: f32,
+    },
+    StageFinished {
+        stage: Stage,
+        percent: f32,
+    },
+    StageFailed {
+        stage: Stage,
+        error: String,
+    },
+    Partition {
+        disk: String,
+        index: usize,
+        action: String,
+        percent: f32,
+    },
+    MkfsOutput {
+        device: String,
+        line: String,
+    },
+    /// The step of the package installation ('update', 'install' or 'done'),
+    /// leaf reports no progress per package
+    Leaf {
+        step: String,
+        percent: Option<f32>,
+    },
+    InstallFinished {
+        error: Option<String>,
+    },
+}
+
+/// Where events get written to, events are dropped if it has no writer
+#[derive(Default)]
+pub struct EventSink {
+    writer: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl fmt::Debug for EventSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EventSink({})", self.writer.is_some())
+    }
+}
+
+impl EventSink {
+    /// Opens the event sink described by the target
+    /// # Arguments
+    /// * `target` - 'stdout', 'fd:<N>' or 'unix:<path>' (connects to a listening socket)
+    pub fn open(target: &str) -> Result<EventSink, SeedError> {
+        let writer: Box<dyn Write + Send> = match target {
+            "stdout" => Box::new(std::io::stdout()),
+            t if t.starts_with("fd:") => {
+                let fd: i32 = match t.trim_start_matches("fd:").parse() {
+                    Ok(fd) => fd,
+                    Err(_) => {
+                        return Err(ValidationError::new(
+                            "events",
+                            format!("'{}' is not a file descriptor", t).as_str(),
+                        )
+                        .into())
+                    }
+                };
+                //The file descriptor stays with the caller, seed writes to a duplicate of it
+                Box::new(crate::libc::dup_fd(fd).map_err(|e| {
+                    ValidationError::new("events", format!("'{}': {}", t, e).as_str())
+                })?)
+            }
+            t if t.starts_with("unix:") => {
+                let path = t.trim_start_matches("unix:");
+                Box::new(
+                    UnixStream::connect(path)
+                        .map_err(|e| SeedError::io(&format!("Failed to connect to {}", path), e))?,
+                )
+            }
+            t => {
+                return Err(ValidationError::new(
+                    "events",
+                    format!("'{}', expected 'stdout', 'fd:<N>' or 'unix:<path>'", t).as_str(),
+                )
+                .into())
+            }
+        };
+
+        Ok(EventSink::from_writer(writer))
+    }
+
+    /// Creates an event sink writing to the provided writer
+    /// # Arguments
+    /// * `writer` - The writer to write the JSON lines to
+    pub fn from_writer(writer: Box<dyn Write + Send>) -> EventSink {
+        EventSink {
+            writer: Some(Mutex::new(writer)),
+        }
+    }
+
+    /// Emits an event, failing to write it does not abort the installation
+    /// # Arguments
+    /// * `event` - The event to emit
+    pub fn emit(&self, event: Event) {
+        let writer = match &self.writer {
+            Some(w) => w,
+            None => return,
+        };
+
+        //The line gets written at once, writers that are shared by several threads keep it in one piece
+        let line = match serde_json::to_string(&event) {
+            Ok(l) => l + "\n",
+            Err(e) => {
+                warn!("Failed to serialize event {:?}: {}", event, e);
+                return;
+            }
+        };
+
+        let mut writer = match writer.lock() {
+            Ok(w) => w,
+            Err(e) => e.into_inner(),
+        };
+        match writer
+            .write_all(line.as_bytes())
+            .and_then(|_| writer.flush())
+        {
+            Ok(_) => (),
+            Err(e) => warn!("Failed to emit event: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+
+    #[test]
+    fn invalid_targets_are_invalid_config() {
+        //Above the descriptor limit, closing one here would race with the other tests
+        for target in ["fd:1000000", "fd:-1", "fd:x", "file:/tmp/events"] {
+            match EventSink::open(target) {
+                Err(e @ SeedError::Validation(_)) => assert_eq!(e.exit_code(), 4),
+                other => panic!("{}: {:?}", target, other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn fd_target_keeps_the_fd_of_the_caller() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+
+        let sink = EventSink::open(&format!("fd:{}", fds[1])).unwrap();
+        sink.emit(Event::InstallFinished { error: None });
+        drop(sink);
+
+        //The descriptor of the caller is still open after the sink is gone
+        assert_ne!(unsafe { libc::fcntl(fds[1], libc::F_GETFD) }, -1);
+        unsafe { libc::close(fds[1]) };
+
+        let mut output = String::new();
+        unsafe { std::fs::File::from_raw_fd(fds[0]) }
+            .read_to_string(&mut output)
+            .unwrap();
+        assert!(output.ends_with("\n"));
+        assert!(output.contains("install_finished"));
+    }
+}
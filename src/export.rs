@@ -0,0 +1,240 @@
+use crate::conf::installfile::*;
+use crate::conf::migrate::INSTALLFILE_VERSION;
+use crate::diskmgr::probe::*;
+use crate::diskmgr::safety::{get_block_name, get_mounts};
+use crate::diskmgr::util::get_part_path;
+use crate::error::SeedError;
+use std::path::Path;
+
+/// Where leaf records the installed packages, relative to the root it installed to
+const LEAF_INSTALLED_DIR: &str = "etc/leaf/installed";
+/// The extension of the files leaf records installed packages in
+const LEAF_INSTALLED_EXT: &str = "leafinstalled";
+
+/// Expresses a byte count exactly, using the largest unit that divides it
+/// # Arguments
+/// * `bytes` - The byte count
+fn exact_part_size(bytes: u64) -> PartSize {
+    for unit in [DataSize::TB, DataSize::GB, DataSize::MB, DataSize::KB] {
+        let unit_bytes = data_size_bytes(1, &unit).unwrap();
+        if bytes.is_multiple_of(unit_bytes) {
+            return PartSize::Size(bytes / unit_bytes, unit);
+        }
+    }
+    PartSize::Size(bytes, DataSize::B)
+}
+
+/// Returns the packages leaf installed to the root
+/// # Arguments
+/// * `root` - The root leaf installed to
+fn get_leaf_packages(root: &str) -> Result<Vec<String>, SeedError> {
+    let dir = Path::new(root).join(LEAF_INSTALLED_DIR);
+    let entries = std::fs::read_dir(&dir)
+        .map_err(|e| SeedError::io(&format!("Failed to read {}", dir.to_string_lossy()), e))?;
+
+    let mut res: Vec<String> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().map(|e| e == LEAF_INSTALLED_EXT) == Some(true))
+        .filter_map(|p| p.file_stem().map(|s| s.to_string_lossy().into_owned()))
+        .collect();
+    res.sort();
+    Ok(res)
+}
+
+/// Describes a probed disk as a disk config that keeps everything on it
+/// # Arguments
+/// * `disk` - The probed disk
+/// * `action` - The action of the disk, 'keep' or 'locked'
+/// * `mounts` - The mounts of the running system
+fn export_disk(disk: ProbedDisk, action: DiskAction, mounts: &[(String, String)]) -> DiskConf {
+    let partitions = disk
+        .partitions
+        .into_iter()
+        //Extended partitions only contain the logical partitions
+        .filter(|p| p.kind != "extended")
+        .map(|p| {
+            let mount = p
+                .path
+                .as_deref()
+                .and_then(|path| get_block_name(path).ok())
+                .and_then(|name| mounts.iter().find(|(n, _)| *n == name))
+                .map(|(_, target)| target.clone());
+
+            PartConf {
+                index: p.index,
+                action: PartAction::Keep,
+                size: Some(exact_part_size(
+                    (p.end - p.start + 1) as u64 * disk.sector_size,
+                )),
+                fs: p.fs,
+                fsargs: None,
+                label: None,
+                fsuuid: None,
+                fstab: mount.as_ref().map(|_| true),
+                mount,
+                //The exact path of the partition, gaps in the numbering are preserved
+                path: Some(get_part_path(&disk.path, p.index)),
+                //Kept partitions leave their table entry as it is
+                part_type: None,
+                name: None,
+                uuid: None,
+                flags: None,
+                kind: None,
+                encrypt: None,
+                mount_point: None,
+                mapping_open: false,
+            }
+        })
+        .collect();
+
+    DiskConf {
+        path: disk.path,
+        action,
+        table: None,
+        alignment: None,
+        partitions,
+    }
+}
+
+/// Creates an installfile that describes the current layout of the disks
+/// # Arguments
+/// * `disks` - The paths to the disks to describe
+/// * `action` - The action of the disks, 'keep' or 'locked'
+/// * `leaf_root` - The root of an existing leaf installation to take the packages from
+pub fn export_installfile(
+    disks: &[String],
+    action: DiskAction,
+    leaf_root: Option<&str>,
+) -> Result<InstallFile, SeedError> {
+    let mounts = get_mounts()?;
+
+    let mut disk_confs: Vec<DiskConf> = Vec::new();
+    for disk in disks {
+        disk_confs.push(export_disk(probe_disk(disk)?, action.clone(), &mounts));
+    }
+
+    let packages = match leaf_root {
+        Some(root) => get_leaf_packages(root)?,
+        None => Vec::new(),
+    };
+
+    Ok(new_installfile(disk_confs, packages))
+}
+
+/// Creates an installfile with the disks and packages, everything else is left at its default
+/// # Arguments
+/// * `disk_confs` - The disks of the installfile
+/// * `packages` - The packages to install
+fn new_installfile(disk_confs: Vec<DiskConf>, packages: Vec<String>) -> InstallFile {
+    InstallFile {
+        version: INSTALLFILE_VERSION,
+        seed: SeedConf {
+            workdir: String::new(),
+            env: EnvConf { chrootcmd: None },
+            disks: disk_confs,
+            installation: InstallationConf {
+                pkglisturl: None,
+                packages,
+                force: None,
+            },
+            fstab: FSTabConf {
+                mode: FSTabMode::UUID,
+                path: None,
+            },
+            systemd: SystemdConf { enable_units: None },
+            symlinks: None,
+            symlink_policy: None,
+            time: None,
+            lang: None,
+            swapfile: None,
+            dry_run: false,
+            plan: Default::default(),
+            journal: Default::default(),
+            events: Default::default(),
+            cancel: Default::default(),
+        },
+    }
+}
+
+/// Writes the installfile in the format and checks that seed reads it back as a valid installfile
+/// # Arguments
+/// * `file` - The installfile to write
+/// * `format` - The format to write
+pub fn render_installfile(
+    file: &InstallFile,
+    format: &InstallFileFormat,
+) -> Result<String, SeedError> {
+    let content = match format {
+        InstallFileFormat::Json => serde_json::to_string_pretty(file).map_err(|e| e.to_string()),
+        InstallFileFormat::Yaml => serde_yaml::to_string(file).map_err(|e| e.to_string()),
+    }
+    .map_err(|e| ParseError::new("<exported>", None, None, &e))?;
+
+    let mut parsed = InstallFile::parse("<exported>", &content, format)?;
+    parsed.validate()?;
+
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A probed GPT disk with an ESP and a root partition
+    fn probed_disk() -> ProbedDisk {
+        let partition = |index: usize, start: i64, end: i64, fs: &str| ProbedPartition {
+            index,
+            path: Some(format!("/dev/sdx{}", index)),
+            kind: "primary".to_owned(),
+            start,
+            end,
+            fs: Some(fs.to_owned()),
+            label: None,
+            uuid: None,
+            name: None,
+        };
+
+        ProbedDisk {
+            path: "/dev/sdx".to_owned(),
+            model: "Test disk".to_owned(),
+            size: 8 * 1024 * 1024 * 1024,
+            sector_size: 512,
+            phys_sector_size: 512,
+            table: Some("gpt".to_owned()),
+            partitions: vec![
+                partition(1, 2048, 1050623, "vfat"),
+                partition(2, 1050624, 16775167, "ext4"),
+            ],
+            free: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn exported_installfile_round_trips() {
+        for action in [DiskAction::Keep, DiskAction::Locked] {
+            for format in [InstallFileFormat::Json, InstallFileFormat::Yaml] {
+                let mut disk = export_disk(probed_disk(), action.clone(), &[]);
+                disk.partitions[1].mount = Some("/".to_owned());
+                disk.partitions[1].fstab = Some(true);
+                let file = new_installfile(vec![disk], vec!["base".to_owned()]);
+
+                let content = render_installfile(&file, &format).unwrap();
+                let mut parsed = InstallFile::parse("test", &content, &format).unwrap();
+                parsed.validate().unwrap();
+
+                let disk = &parsed.seed.disks[0];
+                assert_eq!(disk.path, "/dev/sdx");
+                assert_eq!(disk.action, action);
+                assert_eq!(disk.partitions.len(), 2);
+                assert_eq!(disk.partitions[0].path.as_deref(), Some("/dev/sdx1"));
+                assert!(matches!(
+                    disk.partitions[0].size,
+                    Some(PartSize::Size(512, DataSize::MB))
+                ));
+                assert_eq!(disk.partitions[1].mount.as_deref(), Some("/"));
+                assert_eq!(parsed.seed.installation.packages, vec!["base"]);
+            }
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use crate::conf::seed::*;
+use crate::diskmgr::diskmanager;
+use crate::error::SeedError;
+use crate::event::Event;
+use crate::journal::*;
+use crate::leaf;
+use crate::target;
+use std::sync::atomic::Ordering;
+
+/// Attributes the plain I/O errors of a post-configuration step to the step
+/// # Arguments
+/// * `what` - What the step configures
+/// * `e` - The error of the step
+fn target_error(what: &str, e: SeedError) -> SeedError {
+    match e {
+        SeedError::Io { error, .. } => SeedError::Target {
+            what: what.to_owned(),
+            msg: error.to_string(),
+        },
+        other => other,
+    }
+}
+
+/// Configures the installed system
+/// # Arguments
+/// * `conf` - The seed config
+fn configure_target(conf: &SeedConf) -> Result<(), SeedError> {
+    //The swapfile needs to exist before it enters the fstab
+    if conf.swapfile.is_some() {
+        target::swapfile::create_swapfile(conf).map_err(|e| target_error("swapfile", e))?;
+        info!("Created swapfile");
+    }
+
+    target::fstab::write_fstab(conf).map_err(|e| target_error("fstab", e))?;
+    info!("Generated fstab");
+
+    target::crypttab::write_crypttab(conf).map_err(|e| target_error("crypttab", e))?;
+
+    target::systemd::enable_units(conf).map_err(|e| target_error("systemd units", e))?;
+    info!("Enabled systemd units");
+
+    target::time::apply_timezone(conf).map_err(|e| target_error("timezone", e))?;
+    info!("Configured timezone");
+
+    target::locale::apply_locales(conf).map_err(|e| target_error("locales", e))?;
+    info!("Configured locales");
+
+    target::symlinks::create_symlinks(conf).map_err(|e| target_error("symlinks", e))?;
+    info!("Created symlinks");
+
+    Ok(())
+}
+
+/// Runs a stage of the installation
+/// # Arguments
+/// * `conf` - The seed config
+/// * `stage` - The stage to run
+fn run_stage(conf: &mut SeedConf, stage: Stage) -> Result<(), SeedError> {
+    match stage {
+        Stage::Partitioning => diskmanager::partition_disks(conf),
+        Stage::Mkfs => diskmanager::create_filesystems(conf),
+        Stage::Mounts => diskmanager::mount_disks(conf),
+        Stage::Packages => leaf::leaf_install_system(conf),
+        Stage::PostConfig => configure_target(conf),
+    }
+}
+
+/// Checks that the result of a finished stage is still present and restores runtime state
+/// Mounts do not survive a crash or reboot, they get mounted again
+/// # Arguments
+/// * `conf` - The seed config
+/// * `stage` - The finished stage
+fn resume_stage(conf: &mut SeedConf, stage: Stage) -> Result<(), SeedError> {
+    match stage {
+        Stage::Partitioning => diskmanager::check_partitions(conf),
+        Stage::Mkfs => {
+            diskmanager::open_mappings(conf, false)?;
+            diskmanager::check_filesystems(conf)
+        }
+        Stage::Mounts => {
+            conf.journal.release_mounts();
+            diskmanager::mount_disks(conf)
+        }
+        Stage::Packages | Stage::PostConfig => Ok(()),
+    }
+}
+
+/// Returns if a failure of the stage leaves the disks half configured
+/// # Arguments
+/// * `stage` - The stage to check
+fn is_disk_stage(stage: Stage) -> bool {
+    matches!(stage, Stage::Partitioning | Stage::Mkfs | Stage::Mounts)
+}
+
+/// Installs the system described by the seed config, skipping the stages
+/// the journal records as finished
+/// If a disk stage fails, the original partition tables get restored
+/// # Arguments
+/// * `conf` - The seed config
+pub fn install(conf: &mut SeedConf) -> Result<(), SeedError> {
+    let percent = |i: usize| i as f32 * 100.0 / STAGES.len() as f32;
+
+    for (i, stage) in STAGES.into_iter().enumerate() {
+        if conf.journal.is_finished(stage) {
+            info!("Resuming after finished stage {}", stage);
+            resume_stage(conf, stage).map_err(|e| {
+                SeedError::Journal(format!("Can't resume after stage {}: {}", stage, e))
+            })?;
+            continue;
+        }
+
+        if conf.cancel.load(Ordering::SeqCst) {
+            warn!("Cancelled before stage {}", stage);
+            return Err(SeedError::Cancelled);
+        }
+
+        info!("Running stage {}", stage);
+        conf.events.emit(Event::StageStarted {
+            stage,
+            percent: percent(i),
+        });
+        match run_stage(conf, stage) {
+            Ok(_) => {
+                conf.journal.finish_stage(stage)?;
+                conf.events.emit(Event::StageFinished {
+                    stage,
+                    percent: percent(i + 1),
+                });
+            }
+            Err(e) => {
+                warn!("Stage {} failed", stage);
+                conf.events.emit(Event::StageFailed {
+                    stage,
+                    error: e.to_string(),
+                });
+                if is_disk_stage(stage) {
+                    warn!("Rolling back the changes to the disks...");
+                    match conf
+                        .unmount_partitions()
+                        .and_then(|_| conf.close_mappings())
+                        .and_then(|_| conf.journal.rollback())
+                    {
+                        Ok(_) => info!("Restored the original partition tables"),
+                        Err(e) => error!("Failed to roll back: {}", e.to_string()),
+                    }
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    Ok(())
+}
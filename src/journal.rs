@@ -0,0 +1,338 @@
+use crate::diskmgr::util::get_blkid_tag;
+use crate::error::SeedError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use sys_mount::{unmount, UnmountFlags};
+
+/// The stages of an installation
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Partitioning,
+    Mkfs,
+    Mounts,
+    Packages,
+    PostConfig,
+}
+
+/// The stages of an installation, in the order they run
+pub const STAGES: [Stage; 5] = [
+    Stage::Partitioning,
+    Stage::Mkfs,
+    Stage::Mounts,
+    Stage::Packages,
+    Stage::PostConfig,
+];
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stage::Partitioning => write!(f, "partitioning"),
+            Stage::Mkfs => write!(f, "mkfs"),
+            Stage::Mounts => write!(f, "mounts"),
+            Stage::Packages => write!(f, "package installation"),
+            Stage::PostConfig => write!(f, "post-configuration"),
+        }
+    }
+}
+
+/// The original partition table of a disk, taken before seed changed it
+#[derive(Deserialize, Serialize, Debug)]
+pub struct DiskSnapshot {
+    pub disk: String,
+    /// The 'sfdisk --dump' of the table, None if the disk had no partition table
+    pub table: Option<String>,
+}
+
+/// A filesystem seed started to resize, the original partition table does not match it anymore
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ResizedFilesystem {
+    pub disk: String,
+    pub device: String,
+}
+
+/// Records the changes seed made to the system to be able to undo or resume them
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Journal {
+    /// The installfile the journal belongs to
+    pub installfile: Option<String>,
+    /// The finished stages, in order
+    #[serde(default)]
+    pub stages: Vec<Stage>,
+    pub snapshots: Vec<DiskSnapshot>,
+    /// The mount points seed mounted, parents before their children
+    pub mounts: Vec<String>,
+    /// The finished steps, in order
+    pub steps: Vec<String>,
+    /// The filesystems seed started to resize, their disks can't be rolled back
+    #[serde(default)]
+    pub resized: Vec<ResizedFilesystem>,
+
+    /// The file the journal gets saved to, None to keep it in memory
+    #[serde(skip)]
+    pub path: Option<String>,
+}
+
+/// Checks the output of a command for success
+/// # Arguments
+/// * `what` - The command that got run (for context)
+/// * `output` - The output of the command
+fn check_output(what: &str, output: Output) -> Result<Output, SeedError> {
+    match output.status.success() {
+        true => Ok(output),
+        false => Err(SeedError::command(
+            what,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        )),
+    }
+}
+
+/// Restores the partition table of a disk
+/// # Arguments
+/// * `snapshot` - The snapshot to restore
+fn restore_snapshot(snapshot: &DiskSnapshot) -> Result<(), SeedError> {
+    match &snapshot.table {
+        Some(table) => {
+            let what = format!("sfdisk {}", snapshot.disk);
+            let mut child = Command::new("sfdisk")
+                .arg(&snapshot.disk)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| SeedError::command(&what, e))?;
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(table.as_bytes())
+                .map_err(|e| SeedError::command(&what, e))?;
+            check_output(
+                &what,
+                child
+                    .wait_with_output()
+                    .map_err(|e| SeedError::command(&what, e))?,
+            )?;
+        }
+        //The disk was empty, remove the table seed created
+        None => {
+            let what = format!("wipefs -a {}", snapshot.disk);
+            check_output(
+                &what,
+                Command::new("wipefs")
+                    .arg("-a")
+                    .arg(&snapshot.disk)
+                    .output()
+                    .map_err(|e| SeedError::command(&what, e))?,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+impl Journal {
+    /// Creates a new journal that gets saved to '<workdir>/journal.json'
+    /// # Arguments
+    /// * `workdir` - The seed working directory
+    pub fn new(workdir: &str) -> Journal {
+        Journal {
+            path: Some(format!("{}/journal.json", workdir)),
+            ..Default::default()
+        }
+    }
+
+    /// Loads a journal written by an earlier run
+    /// # Arguments
+    /// * `path` - The path to the journal file
+    pub fn load(path: &str) -> Result<Journal, SeedError> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| SeedError::Journal(format!("Failed to read {}: {}", path, e)))?;
+        let mut journal: Journal = serde_json::from_str(&content)
+            .map_err(|e| SeedError::Journal(format!("Failed to parse {}: {}", path, e)))?;
+        journal.path = Some(path.to_owned());
+        Ok(journal)
+    }
+
+    /// Writes the journal to its file, if it has one
+    pub fn save(&self) -> Result<(), SeedError> {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let write_error =
+            |e: String| SeedError::Journal(format!("Failed to write {}: {}", path, e));
+
+        match Path::new(path).parent() {
+            Some(parent) => {
+                std::fs::create_dir_all(parent).map_err(|e| write_error(e.to_string()))?
+            }
+            None => (),
+        }
+
+        let content = serde_json::to_string_pretty(self).map_err(|e| write_error(e.to_string()))?;
+        std::fs::write(path, content).map_err(|e| write_error(e.to_string()))
+    }
+
+    /// Saves the partition table of a disk before it gets changed
+    /// Only the first snapshot of a disk is kept, it holds the original table
+    /// # Arguments
+    /// * `disk` - The path to the disk
+    pub fn snapshot_disk(&mut self, disk: &str) -> Result<(), SeedError> {
+        if self.snapshots.iter().any(|s| s.disk == disk) {
+            return Ok(());
+        }
+
+        let table = match get_blkid_tag(disk, "PTTYPE")? {
+            Some(_) => {
+                let what = format!("sfdisk --dump {}", disk);
+                let output = check_output(
+                    &what,
+                    Command::new("sfdisk")
+                        .arg("--dump")
+                        .arg(disk)
+                        .output()
+                        .map_err(|e| SeedError::command(&what, e))?,
+                )?;
+                Some(String::from_utf8_lossy(&output.stdout).into_owned())
+            }
+            None => None,
+        };
+
+        debug!("Saved the partition table of {}", disk);
+        self.snapshots.push(DiskSnapshot {
+            disk: disk.to_owned(),
+            table,
+        });
+        self.save()
+    }
+
+    /// Records a mount point seed is about to mount
+    /// # Arguments
+    /// * `target` - The mount point
+    pub fn add_mount(&mut self, target: String) -> Result<(), SeedError> {
+        self.mounts.push(target);
+        self.save()
+    }
+
+    /// Records a finished stage
+    /// # Arguments
+    /// * `stage` - The stage that finished
+    pub fn finish_stage(&mut self, stage: Stage) -> Result<(), SeedError> {
+        info!("Finished stage {}", stage);
+        if !self.stages.contains(&stage) {
+            self.stages.push(stage);
+        }
+        self.save()
+    }
+
+    /// Returns if the stage has been finished
+    /// # Arguments
+    /// * `stage` - The stage to check
+    pub fn is_finished(&self, stage: Stage) -> bool {
+        self.stages.contains(&stage)
+    }
+
+    /// Detaches the recorded mount points that may be left over from an earlier run
+    pub fn release_mounts(&self) {
+        //Children before their parents, mount points that are gone are fine
+        for target in self.mounts.iter().rev() {
+            match unmount(target, UnmountFlags::DETACH) {
+                Ok(_) => debug!("Unmounted {}", target),
+                Err(e) => debug!("Did not unmount {}: {}", target, e),
+            }
+        }
+    }
+
+    /// Records a filesystem that is about to get resized, before the resize starts
+    /// # Arguments
+    /// * `disk` - The path to the disk of the filesystem
+    /// * `device` - The device holding the filesystem
+    pub fn start_resize(&mut self, disk: &str, device: &str) -> Result<(), SeedError> {
+        debug!("Journal: Resizing filesystem on {}", device);
+        self.resized.push(ResizedFilesystem {
+            disk: disk.to_owned(),
+            device: device.to_owned(),
+        });
+        self.save()
+    }
+
+    /// Records a finished step
+    /// # Arguments
+    /// * `step` - The description of the step
+    pub fn finish_step(&mut self, step: String) -> Result<(), SeedError> {
+        debug!("Journal: {}", step);
+        self.steps.push(step);
+        self.save()
+    }
+
+    /// Unmounts the recorded mount points and restores the original partition tables
+    /// The contents of formatted partitions can not be restored
+    /// Disks with a resized filesystem are left as they are, the original table would cut off
+    /// a grown filesystem and seed can not undo the resize
+    pub fn rollback(&mut self) -> Result<(), SeedError> {
+        self.release_mounts();
+        self.mounts.clear();
+        crate::libc::sync();
+
+        let mut failed: Vec<String> = Vec::new();
+        for snapshot in self.snapshots.iter().rev() {
+            match self.resized.iter().find(|r| r.disk == snapshot.disk) {
+                Some(r) => {
+                    error!(
+                        "Not restoring the partition table of {}: The filesystem on {} got resized",
+                        snapshot.disk, r.device
+                    );
+                    failed.push(snapshot.disk.clone());
+                    continue;
+                }
+                None => (),
+            }
+
+            match restore_snapshot(snapshot) {
+                Ok(_) => info!("Restored the partition table of {}", snapshot.disk),
+                Err(e) => {
+                    error!("{}", e.to_string());
+                    failed.push(snapshot.disk.clone());
+                }
+            }
+        }
+        crate::libc::sync();
+
+        if !failed.is_empty() {
+            self.save()?;
+            return Err(SeedError::Journal(format!(
+                "Failed to restore the partition tables of {}",
+                failed.join(", ")
+            )));
+        }
+
+        self.snapshots.clear();
+        self.resized.clear();
+        self.stages.clear();
+        self.finish_step("Rolled back".to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rollback_refuses_disks_with_resized_filesystems() {
+        let mut journal = Journal::default();
+        journal.snapshots.push(DiskSnapshot {
+            disk: "/dev/sda".to_owned(),
+            table: Some("label: gpt\n".to_owned()),
+        });
+        journal.start_resize("/dev/sda", "/dev/sda2").unwrap();
+
+        assert!(journal.rollback().is_err());
+        //The snapshot stays in the journal for a manual rollback
+        assert_eq!(journal.snapshots.len(), 1);
+        assert_eq!(journal.resized.len(), 1);
+    }
+}
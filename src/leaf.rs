@@ -1,8 +1,9 @@
 use crate::conf::seed::*;
+use crate::error::SeedError;
+use crate::event::Event;
 use rleaf::error::*;
 use rleaf::leafconfig::*;
 use rleaf::leafcore::*;
-use std::io;
 
 fn leaf_configure(leaf: &mut Leafcore, conf: &SeedConf) -> Result<(), LeafConfigError> {
     match &conf.installation.pkglisturl {
@@ -32,8 +33,18 @@ fn leaf_configure(leaf: &mut Leafcore, conf: &SeedConf) -> Result<(), LeafConfig
 }
 
 fn leaf_install(leaf: &mut Leafcore, conf: &SeedConf) -> Result<(), LeafCoreError> {
+    conf.events.emit(Event::Leaf {
+        step: "update".to_owned(),
+        percent: Some(0.0),
+    });
     leaf.a_update()?;
 
+    //Leaf does not report its progress while installing
+    conf.events.emit(Event::Leaf {
+        step: "install".to_owned(),
+        percent: None,
+    });
+
     let packages: Vec<&str> = conf
         .installation
         .packages
@@ -42,29 +53,24 @@ fn leaf_install(leaf: &mut Leafcore, conf: &SeedConf) -> Result<(), LeafCoreErro
         .collect();
     leaf.a_install(&packages)?;
 
+    conf.events.emit(Event::Leaf {
+        step: "done".to_owned(),
+        percent: Some(100.0),
+    });
+
     Ok(())
 }
 
-pub fn leaf_install_system(conf: &SeedConf) -> Result<(), io::Error> {
+pub fn leaf_install_system(conf: &SeedConf) -> Result<(), SeedError> {
     let mut leaf = Leafcore::new();
 
     match leaf_configure(&mut leaf, conf) {
         Ok(()) => (),
-        Err(e) => {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Leaf config error: {}", e),
-            ))
-        }
+        Err(e) => return Err(SeedError::LeafConfig(e.to_string())),
     };
 
     match leaf_install(&mut leaf, conf) {
         Ok(()) => Ok(()),
-        Err(e) => {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Leaf error: {}", e),
-            ))
-        }
+        Err(e) => Err(SeedError::LeafInstall(e.to_string())),
     }
 }
@@ -2,3 +2,122 @@ pub fn sync() {
     debug!("Synchronizing I/O operations...");
     unsafe { libc::sync() };
 }
+
+/// Returns the filesystem type (the magic number of statfs) of the filesystem holding the path
+/// # Arguments
+/// * `path` - The path to check
+pub fn get_fs_magic(path: &std::path::Path) -> std::io::Result<u32> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut buf: libc::statfs = unsafe { std::mem::zeroed() };
+    match unsafe { libc::statfs(c_path.as_ptr(), &mut buf) } {
+        0 => Ok(buf.f_type as u32),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+/// Allocates the blocks of a file, unlike a sparse file this leaves no holes
+/// # Arguments
+/// * `file` - The file to allocate
+/// * `len` - The size of the file in bytes
+pub fn fallocate(file: &std::fs::File, len: u64) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len as libc::off_t) } {
+        0 => Ok(()),
+        e => Err(std::io::Error::from_raw_os_error(e)),
+    }
+}
+
+/// Duplicates a file descriptor handed to seed, the original stays with its owner
+/// # Arguments
+/// * `fd` - The file descriptor to duplicate
+/// # Returns
+/// The file of the duplicate, an error if the descriptor is not open
+pub fn dup_fd(fd: std::os::unix::io::RawFd) -> std::io::Result<std::fs::File> {
+    use std::os::unix::io::FromRawFd;
+
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    match unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) } {
+        -1 => Err(std::io::Error::last_os_error()),
+        //The duplicate is owned by nothing else
+        dup => Ok(unsafe { std::fs::File::from_raw_fd(dup) }),
+    }
+}
+
+/// Changes the propagation type (MS_SHARED, MS_SLAVE, MS_PRIVATE...) of a mount point
+/// # Arguments
+/// * `target` - The mount point to change
+/// * `flags` - The propagation type, combined with MS_REC to change the submounts too
+pub fn set_mount_propagation(
+    target: &std::path::Path,
+    flags: libc::c_ulong,
+) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_target = std::ffi::CString::new(target.as_os_str().as_bytes())?;
+    let res = unsafe {
+        libc::mount(
+            std::ptr::null(),
+            c_target.as_ptr(),
+            std::ptr::null(),
+            flags,
+            std::ptr::null(),
+        )
+    };
+    match res {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+/// Restores the settings of a terminal once it goes out of scope, on early returns and panics too
+struct TermiosGuard {
+    fd: std::os::unix::io::RawFd,
+    original: libc::termios,
+}
+
+impl Drop for TermiosGuard {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.original) };
+    }
+}
+
+/// Reads a line from the terminal without echoing it
+/// # Arguments
+/// * `prompt` - The text to show before reading
+/// # Returns
+/// The line without the line break
+pub fn read_password(prompt: &str) -> std::io::Result<String> {
+    use std::io::{BufRead, Write};
+    use std::os::unix::io::AsRawFd;
+
+    let mut tty = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")?;
+    let fd = tty.as_raw_fd();
+
+    let mut term: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut term) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let original = term;
+    term.c_lflag &= !libc::ECHO;
+    term.c_lflag |= libc::ECHONL;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &term) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    //Gives the terminal its echo back, it gets dropped before the terminal gets closed
+    let _guard = TermiosGuard { fd, original };
+
+    tty.write_all(prompt.as_bytes())?;
+    tty.flush()?;
+    let mut line = String::new();
+    std::io::BufReader::new(&tty).read_line(&mut line)?;
+
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
@@ -3,66 +3,239 @@ extern crate pretty_env_logger;
 extern crate log;
 
 mod conf;
+mod daemon;
 mod diskmgr;
+mod error;
+mod event;
+mod export;
+mod install;
+mod journal;
 mod leaf;
 mod libc;
+mod plan;
+mod target;
 
 use conf::installfile::*;
 use diskmgr::*;
+use error::SeedError;
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+
+/// The formats plans and probe results can be printed in
+#[derive(ValueEnum, Clone, Debug)]
+enum OutputFormat {
+    Human,
+    Json,
+}
+
+/// The disk actions an exported installfile can use
+#[derive(ValueEnum, Clone, Debug)]
+enum ExportAction {
+    Keep,
+    Locked,
+}
+
+/// The commands seed can run instead of an installation
+#[derive(Subcommand, Debug)]
+enum SeedCommand {
+    /// Restore the original partition tables recorded in a journal
+    Rollback {
+        /// The journal of the failed installation (<workdir>/journal.json)
+        journal: String,
+    },
+    /// List the block devices with their partitions, filesystems and free space
+    Probe {
+        /// The format to print the devices in
+        #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+        format: OutputFormat,
+    },
+    /// Print an installfile that describes the current layout of the disks
+    Export {
+        /// The disks to describe
+        #[arg(required = true)]
+        disks: Vec<String>,
+
+        /// The action of the exported disks
+        #[arg(long, value_enum, default_value_t = ExportAction::Locked)]
+        action: ExportAction,
+
+        /// Take the package list from the leaf installation at this root
+        #[arg(long, value_name = "ROOT")]
+        leaf_root: Option<String>,
+
+        /// Print the installfile as YAML instead of JSON
+        #[arg(long)]
+        yaml: bool,
+    },
+    /// Serve installation requests of frontends on a Unix socket
+    Daemon {
+        /// The path of the socket to listen on
+        #[arg(long, default_value = "/run/seed.sock")]
+        socket: String,
+
+        /// The permissions of the socket (octal)
+        #[arg(long, default_value = "660")]
+        socket_mode: String,
+    },
+}
 
 /// The AcaciaLinux installer daemon
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
     /// The directory seed should mount its root
     #[arg(short, long, default_value = "./seed_workdir/")]
     workdir: String,
 
-    /// The installfile to process
-    file: String,
-}
+    /// Only print the changes seed would perform, without touching the system
+    #[arg(long)]
+    dry_run: bool,
 
-fn main() {
-    let args = Args::parse();
+    /// Do not ask for confirmation before destroying data on the disks
+    #[arg(long)]
+    yes_destroy: bool,
 
-    if std::env::var("RUST_LOG").is_err() {
-        std::env::set_var("RUST_LOG", "trace")
-    }
-    pretty_env_logger::init();
+    /// The format to print the plan of a dry run in
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    plan_format: OutputFormat,
+
+    /// Continue the installation recorded in the journal of the workdir
+    #[arg(long, conflicts_with = "dry_run")]
+    resume: bool,
+
+    /// Emit progress events as JSON lines to 'stdout', 'fd:<N>' or 'unix:<path>'
+    #[arg(long, value_name = "TARGET")]
+    events: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<SeedCommand>,
 
-    let mut conf: InstallFile =
-        match serde_json::from_str(std::fs::read_to_string(args.file).unwrap().as_str()) {
-            Ok(c) => c,
-            Err(e) => {
-                error!("Failed to parse InstallFile: {}", e.to_string());
-                return;
+    /// The installfile to process (JSON or YAML)
+    #[arg(required = true)]
+    file: Option<String>,
+}
+
+/// Runs seed
+/// # Arguments
+/// * `args` - The command line arguments
+fn run(args: Args) -> Result<(), SeedError> {
+    match &args.command {
+        Some(SeedCommand::Rollback { journal }) => {
+            journal::Journal::load(journal)?.rollback()?;
+            info!("Rolled back the changes recorded in {}", journal);
+            return Ok(());
+        }
+        Some(SeedCommand::Probe { format }) => {
+            let disks = probe::probe_disks();
+            match format {
+                OutputFormat::Human => disks.iter().for_each(|d| print!("{}", d)),
+                OutputFormat::Json => match serde_json::to_string_pretty(&disks) {
+                    Ok(json) => println!("{}", json),
+                    Err(e) => error!("Failed to serialize disks: {}", e),
+                },
             }
-        };
-    conf.seed.workdir = args.workdir;
+            return Ok(());
+        }
+        Some(SeedCommand::Export {
+            disks,
+            action,
+            leaf_root,
+            yaml,
+        }) => {
+            let action = match action {
+                ExportAction::Keep => DiskAction::Keep,
+                ExportAction::Locked => DiskAction::Locked,
+            };
+            let file = export::export_installfile(disks, action, leaf_root.as_deref())?;
+            let format = match yaml {
+                true => InstallFileFormat::Yaml,
+                false => InstallFileFormat::Json,
+            };
+            println!("{}", export::render_installfile(&file, &format)?);
+            return Ok(());
+        }
+        Some(SeedCommand::Daemon {
+            socket,
+            socket_mode,
+        }) => return daemon::run_daemon(socket, socket_mode, &args.workdir),
+        None => (),
+    }
 
-    match conf.validate() {
-        Ok(_) => info!("Installation file is valid"),
-        Err(e) => {
-            error!("{}", e.to_string());
-            return;
+    let file = args.file.unwrap();
+    let mut conf = InstallFile::from_file(&file)?;
+
+    //Identify the installfile by its absolute path in the journal
+    let file = match std::fs::canonicalize(&file) {
+        Ok(p) => p.to_string_lossy().into_owned(),
+        Err(_) => file,
+    };
+
+    if args.resume {
+        let journal_path = format!("{}/journal.json", args.workdir);
+        conf.seed.journal = journal::Journal::load(&journal_path)?;
+        if conf.seed.journal.installfile.as_deref() != Some(file.as_str()) {
+            return Err(SeedError::Journal(format!(
+                "Can't resume: {} belongs to installfile {}",
+                journal_path,
+                conf.seed
+                    .journal
+                    .installfile
+                    .as_deref()
+                    .unwrap_or("<unknown>")
+            )));
         }
+    } else if !args.dry_run {
+        conf.seed.journal = journal::Journal::new(&args.workdir);
+        conf.seed.journal.installfile = Some(file);
+    }
+    if let Some(target) = &args.events {
+        conf.seed.events = event::EventSink::open(target)?;
     }
+    conf.seed.workdir = args.workdir;
+    conf.seed.dry_run = args.dry_run;
 
-    match diskmanager::configure_disks(&mut conf.seed) {
-        Ok(_) => info!("Configured disks"),
-        Err(e) => {
-            error!("{}", e.to_string());
-            return;
+    conf.validate()?;
+    info!("Installation file is valid");
+
+    //The disks of a resumed installation already contain what seed put there
+    if !conf.seed.journal.is_finished(journal::Stage::Partitioning) {
+        safety::check_disks(&conf.seed, args.yes_destroy, true)?;
+    }
+
+    if args.dry_run {
+        diskmanager::configure_disks(&mut conf.seed)?;
+
+        plan::plan_installation(&mut conf.seed);
+        match args.plan_format {
+            OutputFormat::Human => print!("{}", conf.seed.plan.to_human()),
+            OutputFormat::Json => match conf.seed.plan.to_json() {
+                Ok(json) => println!("{}", json),
+                Err(e) => error!("Failed to serialize plan: {}", e),
+            },
         }
-    };
+        return Ok(());
+    }
+
+    install::install(&mut conf.seed)?;
+    info!("Installed system");
+
+    Ok(())
+}
+
+fn main() {
+    let args = Args::parse();
+
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "trace")
+    }
+    pretty_env_logger::init();
 
-    match leaf::leaf_install_system(&conf.seed) {
-        Ok(_) => info!("Installed system"),
+    //The seed config gets dropped (and unmounted) by run() before exiting
+    match run(args) {
+        Ok(_) => (),
         Err(e) => {
-            error!("{}", e.to_string());
-            return;
+            error!("{}", e);
+            std::process::exit(e.exit_code());
         }
     }
 }
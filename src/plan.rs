@@ -0,0 +1,353 @@
+use crate::conf::seed::*;
+use crate::target::crypttab::{has_generated_key, CRYPTTAB_PATH};
+use crate::target::swapfile::{get_swapfile_path, get_swapfile_size};
+use serde::Serialize;
+use std::fmt;
+
+/// A single change seed performs on the system
+#[derive(Serialize, Debug)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum PlanStep {
+    CreateTable {
+        disk: String,
+        table: String,
+    },
+    LoadTable {
+        disk: String,
+    },
+    CreatePartition {
+        disk: String,
+        index: usize,
+        start: i64,
+        end: i64,
+        sector_size: u64,
+    },
+    KeepPartition {
+        disk: String,
+        index: usize,
+        start: i64,
+        end: i64,
+        sector_size: u64,
+    },
+    ResizePartition {
+        disk: String,
+        index: usize,
+        fs: String,
+        start: i64,
+        old_end: i64,
+        /// None if the new end is not known without touching the filesystem
+        end: Option<i64>,
+        sector_size: u64,
+    },
+    SetPartitionAttributes {
+        disk: String,
+        index: usize,
+        attributes: Vec<String>,
+    },
+    CommitTable {
+        disk: String,
+    },
+    EncryptPartition {
+        device: String,
+        mapping: String,
+        command: Vec<String>,
+    },
+    OpenMapping {
+        device: String,
+        mapping: String,
+    },
+    Mkfs {
+        device: String,
+        command: Vec<String>,
+    },
+    GrowFilesystem {
+        device: String,
+        fs: String,
+    },
+    Mount {
+        source: String,
+        target: String,
+    },
+    InstallPackages {
+        pkglisturl: Option<String>,
+        packages: Vec<String>,
+    },
+    PostInstall {
+        description: String,
+    },
+}
+
+/// Formats a sector range as human readable size
+/// # Arguments
+/// * `start` - The first sector
+/// * `end` - The last sector
+/// * `sector_size` - The size of a sector in bytes
+pub fn format_sectors(start: i64, end: i64, sector_size: u64) -> String {
+    format_bytes((end - start + 1) as f64 * sector_size as f64)
+}
+
+/// Formats an amount of bytes as human readable size
+/// # Arguments
+/// * `bytes` - The amount of bytes
+pub fn format_bytes(bytes: f64) -> String {
+    let mib = bytes / (1024.0 * 1024.0);
+    match mib >= 1024.0 {
+        true => format!("{:.2} GiB", mib / 1024.0),
+        false => format!("{:.2} MiB", mib),
+    }
+}
+
+impl fmt::Display for PlanStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlanStep::CreateTable { disk, table } => {
+                write!(f, "Create new {} partition table on {}", table, disk)
+            }
+            PlanStep::LoadTable { disk } => write!(f, "Use existing partition table of {}", disk),
+            PlanStep::CreatePartition {
+                disk,
+                index,
+                start,
+                end,
+                sector_size,
+            } => write!(
+                f,
+                "Create partition {} on {}: sectors {} - {} ({})",
+                index,
+                disk,
+                start,
+                end,
+                format_sectors(*start, *end, *sector_size)
+            ),
+            PlanStep::KeepPartition {
+                disk,
+                index,
+                start,
+                end,
+                sector_size,
+            } => write!(
+                f,
+                "Keep partition {} on {}: sectors {} - {} ({})",
+                index,
+                disk,
+                start,
+                end,
+                format_sectors(*start, *end, *sector_size)
+            ),
+            PlanStep::ResizePartition {
+                disk,
+                index,
+                fs,
+                start,
+                old_end,
+                end,
+                sector_size,
+            } => match end {
+                Some(end) => write!(
+                    f,
+                    "Resize partition {} ({}) on {}: sectors {} - {} ({}) to {} - {} ({})",
+                    index,
+                    fs,
+                    disk,
+                    start,
+                    old_end,
+                    format_sectors(*start, *old_end, *sector_size),
+                    start,
+                    end,
+                    format_sectors(*start, *end, *sector_size)
+                ),
+                None => write!(
+                    f,
+                    "Resize partition {} ({}) on {}: sectors {} - {} ({}) to the minimum size of the filesystem (unknown in a dry run)",
+                    index,
+                    fs,
+                    disk,
+                    start,
+                    old_end,
+                    format_sectors(*start, *old_end, *sector_size)
+                ),
+            },
+            PlanStep::SetPartitionAttributes {
+                disk,
+                index,
+                attributes,
+            } => write!(
+                f,
+                "Set {} of partition {} on {}",
+                attributes.join(", "),
+                index,
+                disk
+            ),
+            PlanStep::CommitTable { disk } => write!(f, "Write partition table of {}", disk),
+            PlanStep::EncryptPartition {
+                device,
+                mapping,
+                command,
+            } => write!(
+                f,
+                "Encrypt {} as {} using '{}'",
+                device,
+                mapping,
+                command.join(" ")
+            ),
+            PlanStep::OpenMapping { device, mapping } => {
+                write!(f, "Open {} as /dev/mapper/{}", device, mapping)
+            }
+            PlanStep::Mkfs { device, command } => {
+                write!(f, "Format {} using '{}'", device, command.join(" "))
+            }
+            PlanStep::GrowFilesystem { device, fs } => {
+                write!(f, "Grow {} filesystem on {} to partition size", fs, device)
+            }
+            PlanStep::Mount { source, target } => write!(f, "Mount {} to {}", source, target),
+            PlanStep::InstallPackages {
+                pkglisturl,
+                packages,
+            } => write!(
+                f,
+                "Install packages {} using leaf (package list: {})",
+                packages.join(", "),
+                pkglisturl.as_deref().unwrap_or("default")
+            ),
+            PlanStep::PostInstall { description } => write!(f, "{}", description),
+        }
+    }
+}
+
+/// The changes seed performs for an installfile, in order
+#[derive(Serialize, Debug, Default)]
+pub struct Plan {
+    pub steps: Vec<PlanStep>,
+}
+
+impl Plan {
+    /// Adds a step to the plan
+    /// # Arguments
+    /// * `step` - The step to add
+    pub fn push(&mut self, step: PlanStep) {
+        debug!("Plan: {}", step);
+        self.steps.push(step);
+    }
+
+    /// Returns the plan in human readable form
+    pub fn to_human(&self) -> String {
+        let mut res = String::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            res.push_str(&format!("{:>3}. {}\n", i + 1, step));
+        }
+        res
+    }
+
+    /// Returns the plan as JSON
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// Records the steps after the disk setup: the package installation and the target configuration
+/// # Arguments
+/// * `conf` - The seed config to plan
+pub fn plan_installation(conf: &mut SeedConf) {
+    let mut steps: Vec<PlanStep> = vec![PlanStep::InstallPackages {
+        pkglisturl: conf.installation.pkglisturl.clone(),
+        packages: conf.installation.packages.clone(),
+    }];
+
+    let mut post_install = |description: String| {
+        steps.push(PlanStep::PostInstall { description });
+    };
+
+    match &conf.swapfile {
+        Some(swapfile) => post_install(format!(
+            "Create swapfile {} ({})",
+            get_swapfile_path(swapfile)
+                .map(|p| p.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            format_bytes(get_swapfile_size(swapfile).unwrap_or(0) as f64)
+        )),
+        None => (),
+    }
+
+    post_install(format!(
+        "Write fstab to {} using {:?} mode",
+        conf.fstab.path.as_deref().unwrap_or("/etc/fstab"),
+        conf.fstab.mode
+    ));
+
+    for disk in &conf.disks {
+        for part in &disk.partitions {
+            let encrypt = match &part.encrypt {
+                Some(e) => e,
+                None => continue,
+            };
+            if has_generated_key(part) {
+                post_install(format!(
+                    "Install the key of {} to {}",
+                    encrypt.name,
+                    encrypt.keyfile.as_deref().unwrap()
+                ));
+            }
+            post_install(format!("Add {} to {}", encrypt.name, CRYPTTAB_PATH));
+        }
+    }
+
+    for unit in conf.systemd.enable_units.iter().flatten() {
+        post_install(format!("Enable systemd unit {}", unit));
+    }
+
+    match &conf.time {
+        Some(time) => post_install(format!("Set timezone to {}", time.timezone)),
+        None => (),
+    }
+
+    match &conf.lang {
+        Some(lang) => {
+            for locale in lang.locales.iter().flatten() {
+                post_install(format!("Generate locale {}", locale));
+            }
+            match &lang.default {
+                Some(default) => post_install(format!("Set default language to {}", default)),
+                None => (),
+            }
+        }
+        None => (),
+    }
+
+    for map in conf.symlinks.iter().flatten() {
+        for (link, target) in map {
+            post_install(format!("Create symlink {} -> {}", link, target));
+        }
+    }
+
+    for step in steps {
+        conf.plan.push(step);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resize(end: Option<i64>) -> PlanStep {
+        PlanStep::ResizePartition {
+            disk: "/dev/sdx".to_owned(),
+            index: 2,
+            fs: "btrfs".to_owned(),
+            start: 2048,
+            old_end: 4196351,
+            end,
+            sector_size: 512,
+        }
+    }
+
+    #[test]
+    fn unknown_resize_end_is_reported() {
+        assert!(resize(Some(2099199))
+            .to_string()
+            .ends_with("to 2048 - 2099199 (1.00 GiB)"));
+        assert!(resize(None).to_string().contains("unknown in a dry run"));
+
+        let json = serde_json::to_value(resize(None)).unwrap();
+        assert_eq!(json["end"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,208 @@
+use crate::conf::seed::*;
+use crate::error::SeedError;
+use std::path::Path;
+use std::process::{Command, Output};
+use sys_mount::*;
+
+/// The API filesystems the builtin chroot binds into the target
+const CHROOT_BIND_MOUNTS: &'static [&'static str] = &["/proc", "/sys", "/dev", "/run"];
+
+/// The propagation of the binds: mounts of the host still reach the target, unmounts in the target stay there
+/// Without it, detaching a bind of a shared mount like /dev would unmount it on the host too
+const CHROOT_BIND_PROPAGATION: libc::c_ulong = libc::MS_SLAVE | libc::MS_REC;
+
+/// The bind mounts of the builtin chroot, unmounted in reverse order when dropped
+struct ChrootMounts {
+    mounts: Vec<Mount>,
+}
+
+impl ChrootMounts {
+    /// Binds the API filesystems into the root directory
+    /// # Arguments
+    /// * `root_dir` - The root directory of the chroot
+    fn new(root_dir: &str) -> Result<ChrootMounts, SeedError> {
+        let mut res = ChrootMounts { mounts: Vec::new() };
+
+        for source in CHROOT_BIND_MOUNTS {
+            let target = format!("{}{}", root_dir, source);
+            std::fs::create_dir_all(&target).map_err(|e| SeedError::mount(&target, e))?;
+
+            debug!("Binding '{}' to '{}'...", source, target);
+            res.mounts.push(
+                Mount::builder()
+                    .flags(MountFlags::BIND | MountFlags::REC)
+                    .mount(source, &target)
+                    .map_err(|e| SeedError::mount(&target, e))?,
+            );
+            crate::libc::set_mount_propagation(Path::new(&target), CHROOT_BIND_PROPAGATION)
+                .map_err(|e| SeedError::mount(&target, e))?;
+        }
+
+        Ok(res)
+    }
+}
+
+impl Drop for ChrootMounts {
+    fn drop(&mut self) {
+        while let Some(mount) = self.mounts.pop() {
+            //The binds are recursive, detach them to release the submounts too
+            match mount.unmount(UnmountFlags::DETACH) {
+                Ok(_) => (),
+                Err(e) => error!("Failed to unmount chroot bind mount: {}", e),
+            }
+        }
+    }
+}
+
+/// Returns the absolute path to the root of the target system
+/// # Arguments
+/// * `conf` - The seed config
+pub fn get_root_dir(conf: &SeedConf) -> Result<String, SeedError> {
+    let root = std::fs::canonicalize(Path::new(&conf.workdir).join("mount"))
+        .map_err(|e| SeedError::io("Failed to find the target root", e))?;
+    Ok(root.to_string_lossy().into_owned())
+}
+
+/// Quotes the string as a single word for sh
+/// # Arguments
+/// * `value` - The string to quote
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Expands the '{ROOTDIR}' and '{COMMAND}' placeholders of the chroot command template
+/// Both get quoted, '{COMMAND}' becomes a shell running the command in the target system
+/// # Arguments
+/// * `template` - The chroot command template
+/// * `root_dir` - The absolute path to the target system
+/// * `command` - The command to execute
+pub fn expand_chroot_command(template: &str, root_dir: &str, command: &str) -> String {
+    let command = format!("/bin/sh -c {}", shell_quote(command));
+
+    //A single pass, the substituted values are not searched for placeholders
+    let mut res = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        res.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(r) = rest.strip_prefix("{ROOTDIR}") {
+            res.push_str(&shell_quote(root_dir));
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("{COMMAND}") {
+            res.push_str(&command);
+            rest = r;
+        } else {
+            res.push('{');
+            rest = &rest[1..];
+        }
+    }
+    res.push_str(rest);
+
+    res
+}
+
+/// Logs the output of the command and turns a failed exit status into an error
+/// # Arguments
+/// * `command` - The command that was executed (for context)
+/// * `output` - The output of the command
+fn check_chroot_output(command: &str, output: Output) -> Result<String, SeedError> {
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+
+    for line in stdout.lines().chain(stderr.lines()) {
+        debug!("[chroot] {}", line);
+    }
+
+    if !output.status.success() {
+        return Err(SeedError::Chroot {
+            command: command.to_owned(),
+            stderr: format!("{}: {}", output.status, stderr.trim().replace("\n", " ")),
+        });
+    }
+
+    Ok(stdout)
+}
+
+/// Executes a shell command inside the target system
+/// Uses 'env.chrootcmd' if set, else the builtin chroot with bound API filesystems
+/// # Arguments
+/// * `conf` - The seed config
+/// * `command` - The command to execute
+/// # Returns
+/// The standard output of the command
+pub fn chroot_exec(conf: &SeedConf, command: &str) -> Result<String, SeedError> {
+    let root_dir = get_root_dir(conf)?;
+    let exec_error = |e: std::io::Error| SeedError::Chroot {
+        command: command.to_owned(),
+        stderr: e.to_string(),
+    };
+
+    let output = match &conf.env.chrootcmd {
+        Some(template) => {
+            let expanded = expand_chroot_command(template, &root_dir, command);
+            debug!("Running '{}'", expanded);
+            Command::new("sh")
+                .arg("-c")
+                .arg(&expanded)
+                .output()
+                .map_err(exec_error)?
+        }
+        None => {
+            debug!("Running '{}' in builtin chroot at {}", command, root_dir);
+            let _mounts = ChrootMounts::new(&root_dir)?;
+            Command::new("chroot")
+                .arg(&root_dir)
+                .arg("/bin/sh")
+                .arg("-c")
+                .arg(command)
+                .output()
+                .map_err(exec_error)?
+        }
+    };
+
+    check_chroot_output(command, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the expanded template and returns the words it got
+    fn run_expanded(template: &str, root_dir: &str, command: &str) -> String {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(expand_chroot_command(template, root_dir, command))
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn expanded_values_stay_single_words() {
+        assert_eq!(
+            run_expanded(
+                "printf '%s|' {ROOTDIR} {COMMAND}",
+                "/tmp/it's {COMMAND}",
+                "echo $HOME; touch 'x'"
+            ),
+            "/tmp/it's {COMMAND}|/bin/sh|-c|echo $HOME; touch 'x'|"
+        );
+    }
+
+    #[test]
+    fn unknown_braces_are_kept() {
+        assert_eq!(
+            expand_chroot_command("run {ROOT} {ROOTDIR}", "/mnt", "true"),
+            "run {ROOT} '/mnt'"
+        );
+    }
+
+    #[test]
+    fn binds_do_not_propagate_back_to_the_host() {
+        assert_eq!(CHROOT_BIND_PROPAGATION, libc::MS_SLAVE | libc::MS_REC);
+        assert_eq!(
+            CHROOT_BIND_PROPAGATION & (libc::MS_SHARED | libc::MS_PRIVATE),
+            0
+        );
+    }
+}
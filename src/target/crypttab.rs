@@ -0,0 +1,206 @@
+use crate::conf::seed::*;
+use crate::diskmgr::crypt::get_keyfile_host_path;
+use crate::diskmgr::util::*;
+use crate::error::SeedError;
+use crate::target::symlinks::normalize_target_path;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// The location of the crypttab on the target system
+pub const CRYPTTAB_PATH: &str = "/etc/crypttab";
+
+/// Returns if seed generated a key for the encrypted partition, kept containers do not get one
+/// # Arguments
+/// * `part` - The encrypted partition
+pub fn has_generated_key(part: &PartConf) -> bool {
+    match &part.encrypt {
+        Some(e) => e.keyfile.is_some() && part.action != PartAction::Keep,
+        None => false,
+    }
+}
+
+/// Resolves the crypttab device (second column) of the encrypted partition
+/// LUKS containers have no label, the UUID is used unless devices are requested
+/// # Arguments
+/// * `mode` - The mode of the fstab
+/// * `device` - The encrypted partition
+fn resolve_device(mode: &FSTabMode, device: &str) -> Result<String, SeedError> {
+    if *mode == FSTabMode::Device {
+        return Ok(device.to_owned());
+    }
+
+    match get_blkid_tag(device, "UUID")? {
+        Some(uuid) => Ok(format!("UUID={}", uuid)),
+        None => Err(SeedError::Target {
+            what: "crypttab".to_owned(),
+            msg: format!("Could not resolve UUID of {}", device),
+        }),
+    }
+}
+
+/// Renders the crypttab for the encrypted partitions of the seed config
+/// # Arguments
+/// * `conf` - The seed config to render the crypttab for
+pub fn render_crypttab(conf: &SeedConf) -> Result<String, SeedError> {
+    let mut res = String::from("# /etc/crypttab: encrypted block devices, generated by seed\n");
+    res.push_str("#\n# <name>\t<device>\t<password>\t<options>\n");
+
+    for disk in &conf.disks {
+        for part in &disk.partitions {
+            let encrypt = match &part.encrypt {
+                Some(e) => e,
+                None => continue,
+            };
+
+            let device = get_part_path(&disk.path, part.index);
+            let password = match has_generated_key(part) {
+                true => encrypt.keyfile.as_deref().unwrap(),
+                false => "none",
+            };
+
+            res.push_str(&format!(
+                "{}\t{}\t{}\tluks\n",
+                encrypt.name,
+                resolve_device(&conf.fstab.mode, &device)?,
+                password
+            ));
+        }
+    }
+
+    Ok(res)
+}
+
+/// Copies a generated key to its 'keyfile' path in the target system, only root can read it
+/// # Arguments
+/// * `root` - The root of the target system
+/// * `workdir` - The working directory the key got generated in
+/// * `encrypt` - The encryption config of the partition
+fn install_keyfile(root: &Path, workdir: &str, encrypt: &EncryptConf) -> Result<(), SeedError> {
+    let keyfile = encrypt.keyfile.as_deref().unwrap();
+    let path = normalize_target_path(Path::new(keyfile))
+        .expect("Invalid keyfile path, was validate() called?");
+    let target = root.join(path.strip_prefix("/").unwrap_or(&path));
+    info!("Installing the key of {} to {}...", encrypt.name, keyfile);
+
+    let key = std::fs::read(get_keyfile_host_path(workdir, encrypt))?;
+
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if target.symlink_metadata().is_ok() {
+        std::fs::remove_file(&target)?;
+    }
+
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o400)
+        .open(&target)?
+        .write_all(&key)?;
+
+    Ok(())
+}
+
+/// Writes the crypttab and the generated keys to the target system, if there are encrypted partitions
+/// # Arguments
+/// * `conf` - The seed config
+pub fn write_crypttab(conf: &SeedConf) -> Result<(), SeedError> {
+    let encrypted = conf
+        .disks
+        .iter()
+        .flat_map(|d| d.partitions.iter())
+        .any(|p| p.encrypt.is_some());
+    if !encrypted {
+        return Ok(());
+    }
+
+    let root = Path::new(&conf.workdir).join("mount");
+
+    for disk in &conf.disks {
+        for part in &disk.partitions {
+            if has_generated_key(part) {
+                install_keyfile(&root, &conf.workdir, part.encrypt.as_ref().unwrap())?;
+            }
+        }
+    }
+
+    let path = root.join(CRYPTTAB_PATH.trim_start_matches('/'));
+    info!("Writing crypttab to {}...", path.to_string_lossy());
+    let content = render_crypttab(conf)?;
+    debug!("Generated crypttab:\n{}", content);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, content)?;
+
+    crate::libc::sync();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::fixture;
+    use crate::conf::validate::Validate;
+
+    /// Parses the installfile with an encrypted root that has the provided keyfile
+    fn parse(keyfile: &str) -> crate::conf::installfile::InstallFile {
+        fixture::parse(&format!(
+            r#"
+disks:
+  - /dev/sdx:
+    action: new
+    table: gpt
+    partitions:
+      - size: max
+        action: create
+        fs: ext4
+        mount: /
+        encrypt:
+          name: cryptroot
+          passphrase: env:SEED_PASSPHRASE
+          keyfile: "{}"
+"#,
+            keyfile
+        ))
+    }
+
+    #[test]
+    fn keyfile_stays_in_the_target() {
+        for keyfile in ["/etc/keys/root.key", "/etc/../root.key"] {
+            assert!(parse(keyfile).validate().is_ok(), "{}", keyfile);
+        }
+        for keyfile in [
+            "root.key",
+            "/../root.key",
+            "/etc/../../root.key",
+            "/",
+            "/my key",
+        ] {
+            assert!(parse(keyfile).validate().is_err(), "{}", keyfile);
+        }
+    }
+
+    #[test]
+    fn keyfile_gets_installed_at_the_normalized_path() {
+        let workdir = std::env::temp_dir().join(format!("seed-keyfile-{}", std::process::id()));
+        let root = workdir.join("mount");
+        let mut file = parse("/etc/../root.key");
+        file.validate().unwrap();
+        let encrypt = file.seed.disks[0].partitions[0].encrypt.as_ref().unwrap();
+
+        let workdir_str = workdir.to_string_lossy().into_owned();
+        let key = get_keyfile_host_path(&workdir_str, encrypt);
+        std::fs::create_dir_all(key.parent().unwrap()).unwrap();
+        std::fs::write(&key, b"key").unwrap();
+
+        install_keyfile(&root, &workdir_str, encrypt).unwrap();
+        assert_eq!(std::fs::read(root.join("root.key")).unwrap(), b"key");
+
+        std::fs::remove_dir_all(&workdir).unwrap();
+    }
+}
@@ -0,0 +1,463 @@
+use crate::conf::seed::*;
+use crate::conf::validate::ValidationError;
+use crate::diskmgr::util::*;
+use crate::error::SeedError;
+use crate::target::swapfile::get_swapfile_path;
+use crate::target::symlinks::normalize_target_path;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The default location of the fstab on the target system
+const FSTAB_DEFAULT_PATH: &str = "/etc/fstab";
+
+/// Looks up a tag (UUID, LABEL, TYPE...) of a device, get_blkid_tag() on the real system
+pub type TagLookup<'a> = &'a dyn Fn(&str, &str) -> Result<Option<String>, SeedError>;
+
+/// A single line in the fstab
+pub struct FSTabEntry {
+    pub spec: String,
+    pub file: String,
+    pub vfstype: String,
+    pub mntops: String,
+    pub freq: u8,
+    pub passno: u8,
+}
+
+impl fmt::Display for FSTabEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{} {}",
+            self.spec, self.file, self.vfstype, self.mntops, self.freq, self.passno
+        )
+    }
+}
+
+/// Returns the path of the fstab inside the target root
+/// # Arguments
+/// * `fstab` - The fstab config
+/// # Returns
+/// The normalized absolute path, None if it escapes the target root
+fn get_fstab_path(fstab: &FSTabConf) -> Option<PathBuf> {
+    let path = fstab.path.as_deref().unwrap_or(FSTAB_DEFAULT_PATH);
+    match path.starts_with('/') {
+        true => normalize_target_path(Path::new(path)),
+        false => None,
+    }
+}
+
+/// Checks the fstab config for a file path inside the target system
+/// # Arguments
+/// * `fstab` - The fstab config
+pub fn validate_fstab(fstab: &FSTabConf) -> Result<(), ValidationError> {
+    match get_fstab_path(fstab) {
+        Some(p) if p != Path::new("/") => Ok(()),
+        _ => Err(ValidationError::new(
+            "fstab",
+            format!(
+                "'{}' is not a file path in the target system",
+                fstab.path.as_deref().unwrap_or(FSTAB_DEFAULT_PATH)
+            )
+            .as_str(),
+        )),
+    }
+}
+
+/// Normalizes the filesystem name to the name the kernel (and mount) expects
+/// # Arguments
+/// * `fs` - The filesystem name as used by mkfs or blkid
+fn normalize_fs_type(fs: &str) -> String {
+    match fs.to_lowercase().as_str() {
+        "fat" | "fat12" | "fat16" | "fat32" | "msdos" => "vfat".to_owned(),
+        other => other.to_owned(),
+    }
+}
+
+/// Returns sane mount options for the provided filesystem
+/// # Arguments
+/// * `fs` - The (normalized) filesystem type
+fn get_mount_options(fs: &str) -> &'static str {
+    match fs {
+        "vfat" | "exfat" => "umask=0077",
+        "ext2" | "ext3" | "ext4" | "btrfs" | "xfs" => "defaults,noatime",
+        _ => "defaults",
+    }
+}
+
+/// Returns the fsck pass number for the provided filesystem
+/// # Arguments
+/// * `fs` - The (normalized) filesystem type
+/// * `mount` - The mount point of the filesystem
+fn get_fsck_passno(fs: &str, mount: &str) -> u8 {
+    match fs {
+        //These filesystems do not use fsck at boot
+        "btrfs" | "xfs" => 0,
+        "ext2" | "ext3" | "ext4" | "vfat" | "exfat" => match mount {
+            "/" => 1,
+            _ => 2,
+        },
+        _ => 0,
+    }
+}
+
+/// Resolves the fstab spec (first column) for the provided device
+/// # Arguments
+/// * `mode` - The mode to use for resolving
+/// * `device` - The device to resolve
+/// * `lookup` - The function to look up the tags of the device with
+fn resolve_spec(mode: &FSTabMode, device: &str, lookup: TagLookup) -> Result<String, SeedError> {
+    let (tag, prefix) = match mode {
+        FSTabMode::UUID => ("UUID", "UUID="),
+        FSTabMode::Label => ("LABEL", "LABEL="),
+        FSTabMode::Device => return Ok(device.to_owned()),
+    };
+
+    match lookup(device, tag)? {
+        //Whitespace separates the fields of the fstab
+        Some(value) if value.contains(char::is_whitespace) => Err(SeedError::Target {
+            what: "fstab".to_owned(),
+            msg: format!(
+                "The {} '{}' of {} contains whitespace, use another fstab mode",
+                tag, value, device
+            ),
+        }),
+        Some(value) => Ok(format!("{}{}", prefix, value)),
+        None => Err(SeedError::Target {
+            what: "fstab".to_owned(),
+            msg: format!("Could not resolve {} of {}", tag, device),
+        }),
+    }
+}
+
+/// Creates the fstab entry that enables swap space
+/// # Arguments
+/// * `spec` - The swap partition or swapfile
+fn swap_entry(spec: String) -> FSTabEntry {
+    FSTabEntry {
+        spec,
+        file: "none".to_owned(),
+        vfstype: "swap".to_owned(),
+        mntops: "defaults".to_owned(),
+        freq: 0,
+        passno: 0,
+    }
+}
+
+/// Collects the fstab entries of all partitions that have 'fstab' set and of the swapfile
+/// # Arguments
+/// * `conf` - The seed config to collect the entries from
+/// * `lookup` - The function to look up the tags of the devices with
+/// # Returns
+/// The entries in the order they need to be mounted, followed by the swap space
+pub fn get_fstab_entries(conf: &SeedConf, lookup: TagLookup) -> Result<Vec<FSTabEntry>, SeedError> {
+    let mut entries: Vec<FSTabEntry> = Vec::new();
+    let mut swap: Vec<FSTabEntry> = Vec::new();
+
+    for disk in &conf.disks {
+        for part in &disk.partitions {
+            if part.fstab != Some(true) {
+                continue;
+            }
+
+            if part.is_swap() {
+                let device = get_fs_path(&disk.path, part);
+                swap.push(swap_entry(resolve_spec(&conf.fstab.mode, &device, lookup)?));
+                continue;
+            }
+
+            let mount = part
+                .mount
+                .as_ref()
+                .expect("'mount' missing from PartConf, was validate() called?");
+            let device = get_fs_path(&disk.path, part);
+
+            //Prefer the configured filesystem, kept partitions need to be probed
+            let fs = match &part.fs {
+                Some(fs) => normalize_fs_type(fs),
+                None => match lookup(&device, "TYPE")? {
+                    Some(fs) => normalize_fs_type(&fs),
+                    None => "auto".to_owned(),
+                },
+            };
+
+            entries.push(FSTabEntry {
+                spec: resolve_spec(&conf.fstab.mode, &device, lookup)?,
+                file: mount.clone(),
+                mntops: get_mount_options(&fs).to_owned(),
+                freq: 0,
+                passno: get_fsck_passno(&fs, mount),
+                vfstype: fs,
+            });
+        }
+    }
+
+    //Parents need to be mounted before their children
+    entries.sort_by_key(|e| Path::new(&e.file).iter().count());
+
+    //The swapfile lives on a mounted filesystem
+    match &conf.swapfile {
+        Some(swapfile) if swapfile.fstab != Some(false) => {
+            let path =
+                get_swapfile_path(swapfile).expect("Invalid swapfile path, was validate() called?");
+            swap.push(swap_entry(path.to_string_lossy().into_owned()));
+        }
+        _ => (),
+    }
+
+    entries.append(&mut swap);
+
+    Ok(entries)
+}
+
+/// Renders the fstab for the seed config
+/// # Arguments
+/// * `conf` - The seed config to render the fstab for
+/// * `lookup` - The function to look up the tags of the devices with
+pub fn render_fstab(conf: &SeedConf, lookup: TagLookup) -> Result<String, SeedError> {
+    let mut res = String::from("# /etc/fstab: static file system information, generated by seed\n");
+    res.push_str("#\n# <file system>\t<mount point>\t<type>\t<options>\t<dump> <pass>\n");
+
+    for entry in get_fstab_entries(conf, lookup)? {
+        res.push_str(&entry.to_string());
+        res.push('\n');
+    }
+
+    Ok(res)
+}
+
+/// Generates the fstab and writes it to the target system
+/// # Arguments
+/// * `conf` - The seed config to generate the fstab for
+pub fn write_fstab(conf: &SeedConf) -> Result<(), SeedError> {
+    let path = get_fstab_path(&conf.fstab).expect("Invalid fstab path, was validate() called?");
+    let path = Path::new(&conf.workdir)
+        .join("mount")
+        .join(path.strip_prefix("/").unwrap_or(&path));
+
+    info!("Writing fstab to {}...", path.to_string_lossy());
+    let content = render_fstab(conf, &get_blkid_tag)?;
+    debug!("Generated fstab:\n{}", content);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, content)?;
+
+    crate::libc::sync();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::fixture;
+    use crate::conf::validate::Validate;
+
+    const DISKS: &str = r#"
+disks:
+  - /dev/sdx:
+    action: new
+    table: gpt
+    partitions:
+      - size: 512M
+        action: create
+        fs: fat
+        mount: /boot/efi
+        fstab: true
+      - size: 4G
+        action: create
+        fs: swap
+        fstab: true
+      - size: max
+        action: create
+        fs: ext4
+        mount: /
+        fstab: true
+  - /dev/sdy:
+    action: keep
+    partitions:
+      - /dev/sdy1:
+        action: keep
+        mount: /var
+        fstab: true
+      - /dev/sdy2:
+        action: keep
+        mount: /srv
+swapfile:
+  size: 1G
+"#;
+
+    /// Parses the installfile with the provided fstab mode
+    fn get_conf(mode: &str) -> SeedConf {
+        fixture::get_conf(&format!("{}\nfstab:\n  mode: {}\n", DISKS, mode))
+    }
+
+    /// Resolves the tags without blkid, kept partitions have xfs on them
+    fn lookup(device: &str, tag: &str) -> Result<Option<String>, SeedError> {
+        let name = device.trim_start_matches("/dev/");
+        Ok(match tag {
+            "UUID" => Some(format!("uuid-{}", name)),
+            "LABEL" => Some(format!("label-{}", name)),
+            "TYPE" => Some("xfs".to_owned()),
+            _ => None,
+        })
+    }
+
+    /// Returns the entries as (spec, file, vfstype, passno)
+    fn get_entries(conf: &SeedConf) -> Vec<(String, String, String, u8)> {
+        get_fstab_entries(conf, &lookup)
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.spec, e.file, e.vfstype, e.passno))
+            .collect()
+    }
+
+    fn entry(spec: &str, file: &str, vfstype: &str, passno: u8) -> (String, String, String, u8) {
+        (spec.to_owned(), file.to_owned(), vfstype.to_owned(), passno)
+    }
+
+    #[test]
+    fn uuid_mode_orders_mounts_and_appends_swap() {
+        assert_eq!(
+            get_entries(&get_conf("uuid")),
+            vec![
+                entry("UUID=uuid-sdx3", "/", "ext4", 1),
+                entry("UUID=uuid-sdy1", "/var", "xfs", 0),
+                entry("UUID=uuid-sdx1", "/boot/efi", "vfat", 2),
+                entry("UUID=uuid-sdx2", "none", "swap", 0),
+                entry("/swapfile", "none", "swap", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn label_mode_uses_labels() {
+        let specs: Vec<String> = get_entries(&get_conf("label"))
+            .into_iter()
+            .map(|e| e.0)
+            .collect();
+        assert_eq!(
+            specs,
+            vec![
+                "LABEL=label-sdx3",
+                "LABEL=label-sdy1",
+                "LABEL=label-sdx1",
+                "LABEL=label-sdx2",
+                "/swapfile"
+            ]
+        );
+    }
+
+    #[test]
+    fn device_mode_renders_device_paths() {
+        let fstab = render_fstab(&get_conf("device"), &lookup).unwrap();
+        let lines: Vec<&str> = fstab.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "/dev/sdx3\t/\text4\tdefaults,noatime\t0 1",
+                "/dev/sdy1\t/var\txfs\tdefaults,noatime\t0 0",
+                "/dev/sdx1\t/boot/efi\tvfat\tumask=0077\t0 2",
+                "/dev/sdx2\tnone\tswap\tdefaults\t0 0",
+                "/swapfile\tnone\tswap\tdefaults\t0 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_tag_is_an_error() {
+        let conf = get_conf("uuid");
+        let res = get_fstab_entries(&conf, &|_: &str, _: &str| Ok(None));
+        assert!(res.is_err());
+    }
+
+    const LOOP_DISKS: &str = r#"
+disks:
+  - /dev/loop0:
+    action: new
+    table: gpt
+    partitions:
+      - size: 512M
+        action: create
+        fs: fat
+        mount: /boot
+        fstab: true
+      - size: max
+        action: create
+        fs: ext4
+        mount: /
+        fstab: true
+"#;
+
+    #[test]
+    fn loop_device_partitions_get_a_separator() {
+        let conf = fixture::get_conf(&format!("{}\nfstab:\n  mode: uuid\n", LOOP_DISKS));
+        assert_eq!(
+            get_entries(&conf),
+            vec![
+                entry("UUID=uuid-loop0p2", "/", "ext4", 1),
+                entry("UUID=uuid-loop0p1", "/boot", "vfat", 2),
+            ]
+        );
+
+        let conf = fixture::get_conf(&format!("{}\nfstab:\n  mode: device\n", LOOP_DISKS));
+        let specs: Vec<String> = get_entries(&conf).into_iter().map(|e| e.0).collect();
+        assert_eq!(specs, vec!["/dev/loop0p2", "/dev/loop0p1"]);
+    }
+
+    #[test]
+    fn fstab_path_stays_in_the_target() {
+        for path in ["/etc/fstab", "/etc/../fstab.seed"] {
+            let mut file = fixture::parse(&format!("fstab:\n  mode: uuid\n  path: {}\n", path));
+            assert!(file.validate().is_ok(), "{}", path);
+        }
+        for path in ["etc/fstab", "/../etc/fstab", "/etc/../../fstab", "/"] {
+            let mut file = fixture::parse(&format!("fstab:\n  mode: uuid\n  path: {}\n", path));
+            assert!(file.validate().is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn mount_points_and_labels_are_single_fields() {
+        let disks = |mount: &str, label: &str| {
+            format!(
+                r#"
+disks:
+  - /dev/sdx:
+    action: new
+    table: gpt
+    partitions:
+      - size: 4G
+        action: create
+        fs: swap
+        label: "{}"
+        fstab: true
+      - size: max
+        action: create
+        fs: ext4
+        mount: "{}"
+        fstab: true
+"#,
+                label, mount
+            )
+        };
+        assert!(fixture::parse(&disks("/", "swap")).validate().is_ok());
+        assert!(fixture::parse(&disks("/my root", "swap"))
+            .validate()
+            .is_err());
+        assert!(fixture::parse(&disks("/\\n/home", "swap"))
+            .validate()
+            .is_err());
+        assert!(fixture::parse(&disks("/..", "swap")).validate().is_err());
+        assert!(fixture::parse(&disks("root", "swap")).validate().is_err());
+        assert!(fixture::parse(&disks("/", "my swap")).validate().is_err());
+    }
+
+    #[test]
+    fn looked_up_label_with_whitespace_is_an_error() {
+        let conf = get_conf("label");
+        let res = get_fstab_entries(&conf, &|_: &str, _: &str| Ok(Some("my disk".to_owned())));
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use super::chroot::*;
+use crate::conf::seed::*;
+use crate::error::SeedError;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// The list of locales glibc supports, relative to a root directory
+const SUPPORTED_LOCALES: &str = "usr/share/i18n/SUPPORTED";
+
+/// Finds the list of supported locales to check against
+/// Prefers the list of the target system and falls back to the installer's one
+/// # Arguments
+/// * `workdir` - The seed working directory
+pub fn find_supported_locales(workdir: &str) -> Option<PathBuf> {
+    [Path::new(workdir).join("mount"), PathBuf::from("/")]
+        .iter()
+        .map(|root| root.join(SUPPORTED_LOCALES))
+        .find(|file| file.is_file())
+}
+
+/// Checks the language config against the list of supported locales
+/// # Arguments
+/// * `supported` - The contents of the SUPPORTED file
+/// * `lang` - The language config to check
+pub fn check_locales(supported: &str, lang: &LangConf) -> Result<(), String> {
+    let supported: Vec<&str> = supported.lines().map(|l| l.trim()).collect();
+
+    for locale in lang.locales.iter().flatten() {
+        if !supported.contains(&locale.trim()) {
+            return Err(format!("Locale '{}' is not supported", locale));
+        }
+    }
+
+    match &lang.default {
+        Some(default) => {
+            //The default needs to be the name of one of the supported locales
+            match supported
+                .iter()
+                .any(|l| l.split_whitespace().next() == Some(default.as_str()))
+            {
+                true => Ok(()),
+                false => Err(format!("Default language '{}' is not supported", default)),
+            }
+        }
+        None => Ok(()),
+    }
+}
+
+/// Validates the language config, if a list of supported locales is available
+/// # Arguments
+/// * `workdir` - The seed working directory
+/// * `lang` - The language config to validate
+pub fn validate_locales(workdir: &str, lang: &LangConf) -> Result<(), ValidationError> {
+    let supported = match find_supported_locales(workdir) {
+        Some(f) => match std::fs::read_to_string(&f) {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(ValidationError::new(
+                    "lang",
+                    format!("Failed to read {}: {}", f.to_string_lossy(), e).as_str(),
+                ))
+            }
+        },
+        None => {
+            warn!("No list of supported locales available, skipping validation of locales");
+            return Ok(());
+        }
+    };
+
+    match check_locales(&supported, lang) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ValidationError::new("lang", e.as_str())),
+    }
+}
+
+/// Adds the locales to the contents of locale.gen, uncommenting existing entries
+/// # Arguments
+/// * `locale_gen` - The current contents of locale.gen
+/// * `locales` - The locales to enable
+pub fn enable_locales(locale_gen: &str, locales: &[String]) -> String {
+    let mut lines: Vec<String> = locale_gen.lines().map(|l| l.to_owned()).collect();
+
+    for locale in locales {
+        let locale = locale.trim();
+
+        //Already enabled
+        if lines.iter().any(|l| l.trim() == locale) {
+            continue;
+        }
+
+        //Commented out
+        match lines
+            .iter_mut()
+            .find(|l| l.trim_start_matches('#').trim() == locale)
+        {
+            Some(l) => *l = locale.to_owned(),
+            None => lines.push(locale.to_owned()),
+        }
+    }
+
+    let mut res = lines.join("\n");
+    res.push('\n');
+    res
+}
+
+/// Writes '/etc/locale.conf', enables the locales in '/etc/locale.gen' and runs locale-gen
+/// # Arguments
+/// * `conf` - The seed config
+pub fn apply_locales(conf: &SeedConf) -> Result<(), SeedError> {
+    let lang = match &conf.lang {
+        Some(l) => l,
+        None => return Ok(()),
+    };
+
+    let etc = Path::new(&conf.workdir).join("mount/etc");
+    std::fs::create_dir_all(&etc)?;
+
+    match &lang.locales {
+        Some(locales) => {
+            info!("Generating locales {:?}...", locales);
+            let locale_gen = etc.join("locale.gen");
+            let content = match std::fs::read_to_string(&locale_gen) {
+                Ok(s) => s,
+                Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(e.into()),
+            };
+            std::fs::write(&locale_gen, enable_locales(&content, locales))?;
+
+            chroot_exec(conf, "locale-gen")?;
+        }
+        None => (),
+    }
+
+    match &lang.default {
+        Some(default) => {
+            info!("Setting default language to {}...", default);
+            std::fs::write(etc.join("locale.conf"), format!("LANG={}\n", default))?;
+        }
+        None => (),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUPPORTED: &str =
+        "C.UTF-8 UTF-8\nde_DE.UTF-8 UTF-8\nde_DE ISO-8859-1\nen_US.UTF-8 UTF-8\n";
+
+    fn lang(default: Option<&str>, locales: &[&str]) -> LangConf {
+        LangConf {
+            default: default.map(|d| d.to_owned()),
+            locales: Some(locales.iter().map(|l| l.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn locales_need_to_be_supported() {
+        assert!(check_locales(SUPPORTED, &lang(None, &["de_DE.UTF-8 UTF-8"])).is_ok());
+        assert!(check_locales(SUPPORTED, &lang(None, &["de_DE.UTF-8"])).is_err());
+        assert!(check_locales(SUPPORTED, &lang(None, &["fr_FR.UTF-8 UTF-8"])).is_err());
+    }
+
+    #[test]
+    fn default_needs_to_be_a_supported_name() {
+        assert!(check_locales(SUPPORTED, &lang(Some("en_US.UTF-8"), &[])).is_ok());
+        assert!(check_locales(SUPPORTED, &lang(Some("en_US.UTF-8 UTF-8"), &[])).is_err());
+        assert!(check_locales(SUPPORTED, &lang(Some("fr_FR.UTF-8"), &[])).is_err());
+    }
+
+    #[test]
+    fn locales_get_uncommented_or_appended_once() {
+        let locale_gen = "# de_DE.UTF-8 UTF-8\n#en_US.UTF-8 UTF-8\nC.UTF-8 UTF-8";
+        let locales = [
+            "en_US.UTF-8 UTF-8".to_owned(),
+            "C.UTF-8 UTF-8".to_owned(),
+            "fr_FR.UTF-8 UTF-8".to_owned(),
+            "fr_FR.UTF-8 UTF-8".to_owned(),
+        ];
+        assert_eq!(
+            enable_locales(locale_gen, &locales),
+            "# de_DE.UTF-8 UTF-8\nen_US.UTF-8 UTF-8\nC.UTF-8 UTF-8\nfr_FR.UTF-8 UTF-8\n"
+        );
+    }
+}
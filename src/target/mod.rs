@@ -0,0 +1,8 @@
+pub mod chroot;
+pub mod crypttab;
+pub mod fstab;
+pub mod locale;
+pub mod swapfile;
+pub mod symlinks;
+pub mod systemd;
+pub mod time;
@@ -0,0 +1,195 @@
+use super::symlinks::normalize_target_path;
+use crate::conf::seed::*;
+use crate::error::SeedError;
+use std::fs::OpenOptions;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The path of the swapfile if 'swapfile.path' is unset
+pub const SWAPFILE_DEFAULT_PATH: &str = "/swapfile";
+
+/// The statfs magic number of btrfs
+const BTRFS_SUPER_MAGIC: u32 = 0x9123683E;
+
+/// Returns the path of the swapfile inside the target system
+/// # Arguments
+/// * `swapfile` - The swapfile config
+/// # Returns
+/// The normalized path, None if it escapes the target root
+pub fn get_swapfile_path(swapfile: &SwapfileConf) -> Option<PathBuf> {
+    normalize_target_path(Path::new(
+        swapfile.path.as_deref().unwrap_or(SWAPFILE_DEFAULT_PATH),
+    ))
+}
+
+/// Returns the size of the swapfile in bytes
+/// # Arguments
+/// * `swapfile` - The swapfile config
+/// # Returns
+/// The size, None if it is not an exact size
+pub fn get_swapfile_size(swapfile: &SwapfileConf) -> Option<u64> {
+    match &swapfile.size {
+        PartSize::Size(count, unit) => data_size_bytes(*count, unit).filter(|b| *b > 0),
+        _ => None,
+    }
+}
+
+/// Checks the swapfile config for a usable path and size
+/// # Arguments
+/// * `swapfile` - The swapfile config
+pub fn validate_swapfile(swapfile: &SwapfileConf) -> Result<(), ValidationError> {
+    let path = swapfile.path.as_deref().unwrap_or(SWAPFILE_DEFAULT_PATH);
+    //The path ends up in the fstab, where whitespace separates the fields
+    match get_swapfile_path(swapfile) {
+        Some(p)
+            if path.starts_with('/')
+                && !path.contains(char::is_whitespace)
+                && p != Path::new("/") => {}
+        _ => {
+            return Err(ValidationError::new(
+                "swapfile",
+                format!("'{}' is not a file path in the target system", path).as_str(),
+            ))
+        }
+    }
+
+    if get_swapfile_size(swapfile).is_none() {
+        return Err(ValidationError::new(
+            "swapfile",
+            "'size' needs to be a size (e.g. '4G')",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Runs a tool on the swapfile
+/// # Arguments
+/// * `program` - The tool to run
+/// * `args` - The arguments, the path of the swapfile gets appended
+/// * `path` - The path of the swapfile on the host
+fn run_tool(program: &str, args: &[&str], path: &Path) -> Result<(), SeedError> {
+    let what = format!("{} {} {}", program, args.join(" "), path.to_string_lossy());
+    debug!("Running {}", what);
+
+    let output = Command::new(program)
+        .args(args)
+        .arg(path)
+        .output()
+        .map_err(|e| SeedError::command(&what, e))?;
+
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(SeedError::command(
+            &what,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        )),
+    }
+}
+
+/// Creates the swapfile from 'swapfile' on the target system
+/// # Arguments
+/// * `conf` - The seed config
+pub fn create_swapfile(conf: &SeedConf) -> Result<(), SeedError> {
+    let swapfile = match &conf.swapfile {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+
+    let path = get_swapfile_path(swapfile).expect("Invalid swapfile path, was validate() called?");
+    let size = get_swapfile_size(swapfile).expect("Invalid swapfile size, was validate() called?");
+    let host_path = Path::new(&conf.workdir)
+        .join("mount")
+        .join(path.strip_prefix("/").unwrap());
+
+    info!(
+        "Creating swapfile {} ({} bytes)...",
+        path.to_string_lossy(),
+        size
+    );
+
+    if let Some(parent) = host_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    //A swapfile of a previous attempt is not in use, it gets created again
+    if host_path.symlink_metadata().is_ok() {
+        std::fs::remove_file(&host_path)?;
+    }
+
+    //Swap can contain anything that was in memory, only root may read it
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&host_path)?;
+
+    //Copy-on-write and compression break swap on btrfs, the NOCOW attribute only applies to empty files
+    let fs_magic = crate::libc::get_fs_magic(&host_path)
+        .map_err(|e| SeedError::io(&host_path.to_string_lossy(), e))?;
+    if fs_magic == BTRFS_SUPER_MAGIC {
+        debug!("Swapfile is on btrfs, disabling copy-on-write");
+        run_tool("chattr", &["+C"], &host_path)?;
+    }
+
+    //Swap can not use the holes of a sparse file
+    crate::libc::fallocate(&file, size).map_err(|e| SeedError::Target {
+        what: "swapfile".to_owned(),
+        msg: format!("Allocating {}: {}", host_path.to_string_lossy(), e),
+    })?;
+    drop(file);
+
+    run_tool("mkswap", &[], &host_path)?;
+    crate::libc::sync();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::fixture;
+
+    /// Parses the swapfile config as it would appear in an installfile
+    fn parse(yaml: &str) -> SwapfileConf {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn swapfile_defaults_to_the_root() {
+        let swapfile = parse("size: 2G");
+        assert!(validate_swapfile(&swapfile).is_ok());
+        assert_eq!(
+            get_swapfile_path(&swapfile),
+            Some(PathBuf::from("/swapfile"))
+        );
+        assert_eq!(get_swapfile_size(&swapfile), Some(2 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn swapfile_path_needs_to_be_a_file_in_the_target() {
+        assert!(validate_swapfile(&parse("{path: /var/swap/../swapfile, size: 1G}")).is_ok());
+        for path in ["swapfile", "/", "/var/..", "/../swapfile", "'/swap file'"] {
+            let swapfile = parse(&format!("{{path: {}, size: 1G}}", path));
+            assert!(validate_swapfile(&swapfile).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn swapfile_needs_an_exact_size() {
+        for size in ["max", "50%", "0G"] {
+            let swapfile = parse(&format!("size: {}", size));
+            assert!(validate_swapfile(&swapfile).is_err(), "{}", size);
+        }
+    }
+
+    #[test]
+    fn swapfile_gets_validated_with_the_installfile() {
+        use crate::conf::validate::Validate;
+        assert!(fixture::parse("swapfile:\n  size: 1G\n").validate().is_ok());
+        assert!(fixture::parse("swapfile:\n  size: max\n")
+            .validate()
+            .is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use crate::conf::seed::*;
+use crate::error::SeedError;
+use std::path::{Component, Path, PathBuf};
+
+/// Resolves '.' and '..' of a path inside the target root without touching the filesystem
+/// # Arguments
+/// * `path` - The absolute path inside the target root
+/// # Returns
+/// The normalized path, None if it escapes the target root
+pub fn normalize_target_path(path: &Path) -> Option<PathBuf> {
+    let mut res = PathBuf::from("/");
+
+    for component in path.components() {
+        match component {
+            Component::RootDir | Component::CurDir => (),
+            Component::ParentDir => {
+                if !res.pop() {
+                    return None;
+                }
+            }
+            Component::Normal(c) => res.push(c),
+            Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(res)
+}
+
+/// Moves the existing file out of the way according to the policy
+/// # Arguments
+/// * `path` - The path of the existing file on the host
+/// * `policy` - The policy to apply
+fn resolve_conflict(path: &Path, policy: &SymlinkPolicy) -> Result<(), SeedError> {
+    let symlink_error = |msg: String| SeedError::Target {
+        what: "symlinks".to_owned(),
+        msg,
+    };
+
+    match policy {
+        SymlinkPolicy::Fail => Err(symlink_error(format!(
+            "Can't create symlink {}: File exists",
+            path.to_string_lossy()
+        ))),
+        SymlinkPolicy::Replace => {
+            debug!("Replacing {}", path.to_string_lossy());
+            match path.symlink_metadata()?.is_dir() {
+                true => Err(symlink_error(format!(
+                    "Can't replace directory {} with a symlink",
+                    path.to_string_lossy()
+                ))),
+                false => Ok(std::fs::remove_file(path)?),
+            }
+        }
+        SymlinkPolicy::Backup => {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(".seed-backup");
+            let backup = PathBuf::from(backup);
+
+            if backup.symlink_metadata().is_ok() {
+                return Err(symlink_error(format!(
+                    "Can't back up {}: {} exists",
+                    path.to_string_lossy(),
+                    backup.to_string_lossy()
+                )));
+            }
+
+            debug!(
+                "Backing up {} to {}",
+                path.to_string_lossy(),
+                backup.to_string_lossy()
+            );
+            Ok(std::fs::rename(path, backup)?)
+        }
+    }
+}
+
+/// Creates a symlink inside the target root
+/// # Arguments
+/// * `root` - The target root on the host
+/// * `link` - The absolute path of the link inside the target root
+/// * `target` - The path the link points to, relative paths are relative to the link
+/// * `policy` - What to do if the link path is occupied
+pub fn create_symlink(
+    root: &Path,
+    link: &str,
+    target: &str,
+    policy: &SymlinkPolicy,
+) -> Result<(), SeedError> {
+    let invalid = |msg: &str| SeedError::Target {
+        what: "symlinks".to_owned(),
+        msg: format!("Symlink {} -> {}: {}", link, target, msg),
+    };
+
+    if !link.starts_with('/') {
+        return Err(invalid("The link path needs to be absolute"));
+    }
+    let link_path = normalize_target_path(Path::new(link))
+        .ok_or_else(|| invalid("Link escapes the target root"))?;
+
+    //Resolve the target the way the installed system will, not the installer
+    let link_dir = link_path.parent().unwrap_or(Path::new("/"));
+    let target_path = normalize_target_path(&link_dir.join(target))
+        .ok_or_else(|| invalid("Target escapes the target root"))?;
+
+    let host_link = root.join(link_path.strip_prefix("/").unwrap());
+    let host_target = root.join(target_path.strip_prefix("/").unwrap());
+
+    if host_target.symlink_metadata().is_err() {
+        warn!(
+            "Symlink {} -> {}: Target does not exist in the target system",
+            link, target
+        );
+    }
+
+    //Check for existing files in the way
+    match std::fs::read_link(&host_link) {
+        Ok(existing) if existing == Path::new(target) => {
+            debug!("Symlink {} -> {} already exists", link, target);
+            return Ok(());
+        }
+        _ => (),
+    }
+    if host_link.symlink_metadata().is_ok() {
+        resolve_conflict(&host_link, policy)?;
+    }
+
+    match host_link.parent() {
+        Some(parent) => std::fs::create_dir_all(parent)?,
+        None => (),
+    }
+
+    debug!("Creating symlink {} -> {}", link, target);
+    Ok(std::os::unix::fs::symlink(target, &host_link)?)
+}
+
+/// Creates the symlinks from 'symlinks' in the target system
+/// # Arguments
+/// * `conf` - The seed config
+pub fn create_symlinks(conf: &SeedConf) -> Result<(), SeedError> {
+    let symlinks = match &conf.symlinks {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+
+    let root = Path::new(&conf.workdir).join("mount");
+    let policy = conf.symlink_policy.as_ref().unwrap_or(&SymlinkPolicy::Fail);
+
+    for map in symlinks {
+        for (link, target) in map {
+            info!("Creating symlink {} -> {}...", link, target);
+            create_symlink(&root, link, target, policy)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an empty target root for the test
+    fn create_root(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("seed-symlinks-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("etc")).unwrap();
+        std::fs::write(root.join("etc/existing"), b"existing").unwrap();
+        root
+    }
+
+    #[test]
+    fn paths_get_normalized_inside_the_root() {
+        let normalize = |p: &str| normalize_target_path(Path::new(p));
+        assert_eq!(normalize("/etc/./foo"), Some(PathBuf::from("/etc/foo")));
+        assert_eq!(
+            normalize("/etc/../usr/bin"),
+            Some(PathBuf::from("/usr/bin"))
+        );
+        assert_eq!(normalize("/usr/.."), Some(PathBuf::from("/")));
+        assert_eq!(normalize("/.."), None);
+        assert_eq!(normalize("/etc/../../usr"), None);
+    }
+
+    #[test]
+    fn fail_policy_keeps_existing_files() {
+        let root = create_root("fail");
+        let res = create_symlink(&root, "/etc/existing", "/usr/bin/env", &SymlinkPolicy::Fail);
+        assert!(res.is_err());
+        assert_eq!(
+            std::fs::read(root.join("etc/existing")).unwrap(),
+            b"existing"
+        );
+
+        //An identical symlink is no conflict
+        create_symlink(&root, "/etc/link", "existing", &SymlinkPolicy::Fail).unwrap();
+        create_symlink(&root, "/etc/link", "existing", &SymlinkPolicy::Fail).unwrap();
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn replace_policy_replaces_files() {
+        let root = create_root("replace");
+        create_symlink(
+            &root,
+            "/etc/existing",
+            "/usr/bin/env",
+            &SymlinkPolicy::Replace,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_link(root.join("etc/existing")).unwrap(),
+            Path::new("/usr/bin/env")
+        );
+
+        //Directories are not replaced
+        let res = create_symlink(&root, "/etc", "/usr/etc", &SymlinkPolicy::Replace);
+        assert!(res.is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn backup_policy_keeps_a_copy() {
+        let root = create_root("backup");
+        create_symlink(
+            &root,
+            "/etc/existing",
+            "/usr/bin/env",
+            &SymlinkPolicy::Backup,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read(root.join("etc/existing.seed-backup")).unwrap(),
+            b"existing"
+        );
+        assert!(std::fs::read_link(root.join("etc/existing")).is_ok());
+
+        //An existing backup is never overwritten
+        let res = create_symlink(
+            &root,
+            "/etc/existing",
+            "/usr/bin/true",
+            &SymlinkPolicy::Backup,
+        );
+        assert!(res.is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn links_and_targets_stay_in_the_root() {
+        let root = create_root("escape");
+        assert!(create_symlink(&root, "/../escape", "/etc", &SymlinkPolicy::Fail).is_err());
+        assert!(create_symlink(&root, "/etc/link", "../../..", &SymlinkPolicy::Fail).is_err());
+        assert!(create_symlink(&root, "etc/link", "/etc", &SymlinkPolicy::Fail).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
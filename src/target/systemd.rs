@@ -0,0 +1,85 @@
+use super::chroot::*;
+use crate::conf::seed::*;
+use crate::error::SeedError;
+
+/// The longest unit name systemd accepts
+const UNIT_NAME_MAX: usize = 255;
+
+/// Checks that the unit name only uses the characters systemd allows in unit names
+/// # Arguments
+/// * `unit` - The unit name (e.g. 'sshd', 'getty@tty1.service')
+pub fn check_unit_name(unit: &str) -> Result<(), String> {
+    let valid = !unit.is_empty()
+        && unit.len() <= UNIT_NAME_MAX
+        && !unit.starts_with('-')
+        && unit
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || ":-_.\\@".contains(c));
+
+    match valid {
+        true => Ok(()),
+        false => Err(format!("'{}' is not a valid unit name", unit)),
+    }
+}
+
+/// Validates the names of the units to enable
+/// # Arguments
+/// * `systemd` - The systemd config to validate
+pub fn validate_units(systemd: &SystemdConf) -> Result<(), ValidationError> {
+    for unit in systemd.enable_units.iter().flatten() {
+        match check_unit_name(unit) {
+            Ok(_) => (),
+            Err(e) => return Err(ValidationError::new("systemd", e.as_str())),
+        }
+    }
+
+    Ok(())
+}
+
+/// Enables the systemd units listed in 'systemd.enable-units' in the target system
+/// # Arguments
+/// * `conf` - The seed config
+pub fn enable_units(conf: &SeedConf) -> Result<(), SeedError> {
+    let units = match &conf.systemd.enable_units {
+        Some(u) => u,
+        None => return Ok(()),
+    };
+
+    for unit in units {
+        info!("Enabling systemd unit {}...", unit);
+        chroot_exec(
+            conf,
+            format!("systemctl enable {}", shell_quote(unit)).as_str(),
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_names_follow_systemd_rules() {
+        for unit in [
+            "sshd",
+            "NetworkManager",
+            "getty@tty1.service",
+            "dev-sda1.swap",
+            "a\\x2db.mount",
+        ] {
+            assert!(check_unit_name(unit).is_ok(), "{}", unit);
+        }
+        for unit in [
+            "",
+            "--now",
+            "ssh d",
+            "sshd'; rm -rf /",
+            "$(reboot)",
+            &"a".repeat(256),
+        ] {
+            assert!(check_unit_name(unit).is_err(), "{}", unit);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::conf::seed::*;
+use crate::error::SeedError;
+use std::path::{Component, Path, PathBuf};
+
+/// The location of the timezone database relative to a root directory
+const ZONEINFO_DIR: &str = "usr/share/zoneinfo";
+
+/// Finds the zoneinfo data to check timezones against
+/// Prefers the data of the target system and falls back to the installer's one
+/// # Arguments
+/// * `workdir` - The seed working directory
+pub fn find_zoneinfo_dir(workdir: &str) -> Option<PathBuf> {
+    [Path::new(workdir).join("mount"), PathBuf::from("/")]
+        .iter()
+        .map(|root| root.join(ZONEINFO_DIR))
+        .find(|dir| dir.is_dir())
+}
+
+/// Checks that the timezone exists in the zoneinfo directory
+/// # Arguments
+/// * `zoneinfo_dir` - The zoneinfo directory to check in
+/// * `timezone` - The timezone to check (e.g. 'Europe/Berlin')
+pub fn check_timezone(zoneinfo_dir: &Path, timezone: &str) -> Result<(), String> {
+    //Only allow plain paths below the zoneinfo directory
+    if !Path::new(timezone)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(format!("'{}' is not a valid timezone name", timezone));
+    }
+
+    match zoneinfo_dir.join(timezone).is_file() {
+        true => Ok(()),
+        false => Err(format!(
+            "Timezone '{}' does not exist in {}",
+            timezone,
+            zoneinfo_dir.to_string_lossy()
+        )),
+    }
+}
+
+/// Validates the timezone, if zoneinfo data is available
+/// # Arguments
+/// * `workdir` - The seed working directory
+/// * `time` - The time config to validate
+pub fn validate_timezone(workdir: &str, time: &TimeConf) -> Result<(), ValidationError> {
+    let zoneinfo_dir = match find_zoneinfo_dir(workdir) {
+        Some(d) => d,
+        None => {
+            warn!("No zoneinfo data available, skipping validation of timezone");
+            return Ok(());
+        }
+    };
+
+    match check_timezone(&zoneinfo_dir, &time.timezone) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(ValidationError::new("time", e.as_str())),
+    }
+}
+
+/// Links '/etc/localtime' of the target system to the configured timezone
+/// # Arguments
+/// * `conf` - The seed config
+pub fn apply_timezone(conf: &SeedConf) -> Result<(), SeedError> {
+    let time = match &conf.time {
+        Some(t) => t,
+        None => return Ok(()),
+    };
+
+    let root = Path::new(&conf.workdir).join("mount");
+
+    //Check against the data that actually got installed
+    match check_timezone(&root.join(ZONEINFO_DIR), &time.timezone) {
+        Ok(_) => (),
+        Err(e) => {
+            return Err(SeedError::Target {
+                what: "timezone".to_owned(),
+                msg: e,
+            })
+        }
+    }
+
+    let localtime = root.join("etc/localtime");
+    let target = format!("../{}/{}", ZONEINFO_DIR, time.timezone);
+
+    info!("Setting timezone to {}...", time.timezone);
+    std::fs::create_dir_all(root.join("etc"))?;
+    if localtime.symlink_metadata().is_ok() {
+        std::fs::remove_file(&localtime)?;
+    }
+    std::os::unix::fs::symlink(&target, &localtime)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timezone_needs_to_exist_below_the_zoneinfo_dir() {
+        let dir = std::env::temp_dir().join(format!("seed-zoneinfo-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("Europe")).unwrap();
+        std::fs::write(dir.join("Europe/Berlin"), b"TZif").unwrap();
+        std::fs::write(dir.join("UTC"), b"TZif").unwrap();
+
+        assert!(check_timezone(&dir, "Europe/Berlin").is_ok());
+        assert!(check_timezone(&dir, "UTC").is_ok());
+        assert!(check_timezone(&dir, "Europe/Paris").is_err());
+        //Directories are no timezones
+        assert!(check_timezone(&dir, "Europe").is_err());
+        assert!(check_timezone(&dir, "../UTC").is_err());
+        assert!(check_timezone(&dir, "Europe/../UTC").is_err());
+        assert!(check_timezone(&dir, "/etc/passwd").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}